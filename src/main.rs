@@ -1,20 +1,248 @@
+// The code base writes explicit `return` statements and `field: field`
+// initialisers throughout, which predates this lint configuration. Allowing
+// the two style lints keeps `cargo clippy -- -D warnings` usable without
+// rewriting every function, all other lints stay enabled.
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
 use std::env;
+use std::fs;
+use std::path::Path;
 use std::process;
 mod padseq;
-use padseq::sequencer::Sequencer;
+use padseq::autosave::{self, DEFAULT_BACKUPS};
+use padseq::library::PatternLibrary;
+use padseq::project::ProjectLibrary;
+use padseq::sequencer::{Sequencer, DEFAULT_NUMBER_OF_INSTRUMENTS};
+use padseq::session::Session;
+use padseq::smf::{self, ImportOptions, PatternSelection, Quantization};
+use padseq::thru::ThruConfig;
 use padseq::ui::UI;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let file_path = match args.len() {
-        1 => None,
-        2 => Some(args[1].clone()),
-        _ => {
-            println!("Expects only one optional argument, the path to a session.json file");
+const USAGE: &str = "Usage:
+    padseq [--keyboard <port>] [--thru <thru.json>] [--library <dir>]
+           [session.json | project-dir]
+    padseq export <session.json> <output.mid> [pattern]
+    padseq import <input.mid> <session.json> <instrument> <pattern>
+                  [--track <n>] [--bar <n>] [--quantize nearest|floor]
+    padseq validate <session.json>";
+
+fn usage() -> ! {
+    println!("{}", USAGE);
+    process::exit(1);
+}
+
+fn read_session(path: &str) -> Session {
+    let json = match fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) => {
+            println!("Unable to read {}: {}", path, e);
             process::exit(1);
         }
     };
-    let mut ui = UI::new(Sequencer::new(file_path));
+    match Session::from_json(&json) {
+        Ok(session) => session,
+        Err(e) => {
+            println!("Unable to parse {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Prints the problems of the session, returns whether there were any.
+fn report_problems(path: &str, session: &Session) -> bool {
+    let problems = session.validate();
+    for problem in &problems {
+        println!("{}: {}", path, problem);
+    }
+    return !problems.is_empty();
+}
+
+fn load_session(path: &str) -> Session {
+    let session = read_session(path);
+    if report_problems(path, &session) {
+        process::exit(1);
+    }
+    return session;
+}
+
+fn validate(args: &[String]) {
+    if args.len() != 1 {
+        usage();
+    }
+    if report_problems(&args[0], &read_session(&args[0])) {
+        process::exit(1);
+    }
+    println!("{}: ok", args[0]);
+}
+
+fn export(args: &[String]) {
+    let selection = match args.len() {
+        2 => PatternSelection::Active,
+        3 => match args[2].parse::<usize>() {
+            Ok(pattern) => PatternSelection::Index(pattern),
+            Err(_) => usage(),
+        },
+        _ => usage(),
+    };
+    let session = load_session(&args[0]);
+    if let Err(e) = smf::write_session(&session, &selection, &args[1]) {
+        println!("Unable to write {}: {}", args[1], e);
+        process::exit(1);
+    }
+}
+
+fn parse_number(value: Option<&String>) -> usize {
+    match value.map(|x| x.parse::<usize>()) {
+        Some(Ok(number)) => number,
+        _ => usage(),
+    }
+}
+
+fn import(args: &[String]) {
+    if args.len() < 4 {
+        usage();
+    }
+    let instrument = parse_number(args.get(2));
+    let pattern = parse_number(args.get(3));
+    let mut options = ImportOptions {
+        track: None,
+        bar: 0,
+        quantization: Quantization::Nearest,
+    };
+    let mut rest = args[4..].iter();
+    while let Some(option) = rest.next() {
+        match option.as_str() {
+            "--track" => options.track = Some(parse_number(rest.next())),
+            "--bar" => options.bar = parse_number(rest.next()),
+            "--quantize" => {
+                options.quantization = match rest.next().map(|x| x.as_str()) {
+                    Some("nearest") => Quantization::Nearest,
+                    Some("floor") => Quantization::Floor,
+                    _ => usage(),
+                }
+            }
+            _ => usage(),
+        }
+    }
+
+    let the_pattern = match smf::read_pattern(&args[0], &options) {
+        Ok(the_pattern) => the_pattern,
+        Err(e) => {
+            println!("Unable to import {}: {}", args[0], e);
+            process::exit(1);
+        }
+    };
+    let session_path = &args[1];
+    let mut session = match Path::new(session_path).exists() {
+        true => load_session(session_path),
+        false => Session::new(DEFAULT_NUMBER_OF_INSTRUMENTS),
+    };
+    if instrument >= session.number_of_instruments() {
+        println!(
+            "The session has only {} instruments",
+            session.number_of_instruments()
+        );
+        process::exit(1);
+    }
+    session
+        .get_instrument_mut(instrument)
+        .set_pattern(pattern, &the_pattern);
+    let json = session.to_json().unwrap();
+    if let Err(e) = autosave::write_atomically(session_path, DEFAULT_BACKUPS, &json) {
+        println!("Unable to write {}: {}", session_path, e);
+        process::exit(1);
+    }
+}
+
+fn load_thru_config(path: Option<&String>) -> ThruConfig {
+    let path = match path {
+        Some(path) => path,
+        None => usage(),
+    };
+    let json = match fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) => {
+            println!("Unable to read {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    match ThruConfig::from_json(&json) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Unable to parse {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn load_pattern_library(path: Option<&String>) -> PatternLibrary {
+    let path = match path {
+        Some(path) => path,
+        None => usage(),
+    };
+    match PatternLibrary::load(path) {
+        Ok(library) => library,
+        Err(e) => {
+            println!("Unable to load the pattern library {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn run(args: &[String]) {
+    let mut file_path = None;
+    let mut keyboard_port = None;
+    let mut thru_config = None;
+    let mut library = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--keyboard" => keyboard_port = Some(parse_number(rest.next()) as u8),
+            "--thru" => thru_config = Some(load_thru_config(rest.next())),
+            "--library" => library = Some(load_pattern_library(rest.next())),
+            _ if file_path.is_none() => file_path = Some(arg.clone()),
+            _ => usage(),
+        }
+    }
+    // a directory holds a session file per project, the first one is played
+    let mut projects = None;
+    if let Some(directory) = file_path.clone().filter(|x| Path::new(x).is_dir()) {
+        let project_library = match ProjectLibrary::scan(&directory) {
+            Ok(project_library) => project_library,
+            Err(e) => {
+                println!("Unable to read {}: {}", directory, e);
+                process::exit(1);
+            }
+        };
+        file_path = project_library.get_path(0).map(|x| x.to_string());
+        projects = Some(project_library);
+    }
+    let session = match &file_path {
+        Some(path) if Path::new(path).exists() => load_session(path),
+        _ => Session::new(DEFAULT_NUMBER_OF_INSTRUMENTS),
+    };
+    let mut sequencer = Sequencer::new(session, file_path);
+    if let Some(projects) = projects {
+        sequencer.set_projects(projects, 0);
+    }
+    if let Some(library) = library {
+        sequencer.set_pattern_library(library);
+    }
+    sequencer.set_keyboard_port(keyboard_port);
+    if let Some(config) = thru_config {
+        sequencer.set_thru(config);
+    }
+    let mut ui = UI::new(sequencer);
     print!("Connected");
     ui.run();
 }
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(|x| x.as_str()) {
+        Some("export") => export(&args[2..]),
+        Some("import") => import(&args[2..]),
+        Some("validate") => validate(&args[2..]),
+        _ => run(&args[1..]),
+    }
+}
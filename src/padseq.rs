@@ -1,4 +1,13 @@
+pub mod autosave;
+pub mod generators;
+pub mod history;
+pub mod library;
 pub mod midi;
+pub mod project;
+pub mod random;
+pub mod scale;
 pub mod sequencer;
 pub mod session;
+pub mod smf;
+pub mod thru;
 pub mod ui;
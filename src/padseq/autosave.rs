@@ -0,0 +1,213 @@
+use super::session::Session;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_BACKUPS: usize = 3;
+/// Changes are written once no other change came in for this long.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+/// A steady stream of changes is still written at least this often.
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+enum Message {
+    Save(Session),
+    Flush(Sender<()>),
+    SetPath(String),
+}
+
+/// Writes the session in a background thread so the timing loop never waits
+/// on the disk.
+///
+/// The file is written to a temporary file first and renamed over the session
+/// file, so a crash never leaves a half written session. The backups are
+/// rotated on the first write to a path only, so `<path>.1` (the newest) to
+/// `<path>.<backups>` keep the sessions as they were loaded rather than the
+/// last few edits.
+pub struct Autosave {
+    sender: Option<Sender<Message>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Autosave {
+    pub fn new(path: &str, backups: usize) -> Autosave {
+        let (sender, receiver) = mpsc::channel();
+        let path = path.to_string();
+        let writer = thread::spawn(move || run_writer(path, backups, receiver));
+        Autosave {
+            sender: Some(sender),
+            writer: Some(writer),
+        }
+    }
+
+    /// Schedules the session to be written.
+    pub fn save(&self, session: &Session) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(Message::Save(session.clone()));
+        }
+    }
+
+    /// Writes the following sessions to another file, pending changes still
+    /// go to the current one.
+    pub fn set_path(&self, path: &str) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(Message::SetPath(path.to_string()));
+        }
+    }
+
+    /// Writes pending changes right away and waits until they are on disk.
+    pub fn flush(&self) {
+        if let Some(sender) = &self.sender {
+            let (done, wait) = mpsc::channel();
+            if sender.send(Message::Flush(done)).is_ok() {
+                let _ = wait.recv();
+            }
+        }
+    }
+}
+
+impl Drop for Autosave {
+    fn drop(&mut self) {
+        // closing the channel writes what is pending and stops the writer
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn run_writer(mut path: String, backups: usize, receiver: Receiver<Message>) {
+    let mut pending: Option<Session> = None;
+    let mut first_change = Instant::now();
+    let mut last_change = Instant::now();
+    // the backups of the path are rotated by its first write
+    let mut rotate = true;
+    loop {
+        let message = match pending {
+            Some(_) => {
+                let deadline = (last_change + DEBOUNCE).min(first_change + MAX_DELAY);
+                receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match message {
+            Ok(Message::Save(session)) => {
+                if pending.is_none() {
+                    first_change = Instant::now();
+                }
+                last_change = Instant::now();
+                pending = Some(session);
+            }
+            Ok(Message::Flush(done)) => {
+                if let Some(session) = pending.take() {
+                    write(&path, backups, rotate, &session);
+                    rotate = false;
+                }
+                let _ = done.send(());
+            }
+            Ok(Message::SetPath(new_path)) => {
+                if let Some(session) = pending.take() {
+                    write(&path, backups, rotate, &session);
+                    rotate = false;
+                }
+                if new_path != path {
+                    rotate = true;
+                }
+                path = new_path;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(session) = pending.take() {
+                    write(&path, backups, rotate, &session);
+                    rotate = false;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                if let Some(session) = pending.take() {
+                    write(&path, backups, rotate, &session);
+                }
+                return;
+            }
+        }
+    }
+}
+
+fn write(path: &str, backups: usize, rotate: bool, session: &Session) {
+    let data = match session.to_json() {
+        Ok(data) => data,
+        Err(e) => {
+            println!("Unable to serialize the session: {}", e);
+            return;
+        }
+    };
+    let backups = if rotate { backups } else { 0 };
+    if let Err(e) = write_atomically(path, backups, &data) {
+        println!("Unable to write {}: {}", path, e);
+    }
+}
+
+fn backup_path(path: &str, index: usize) -> String {
+    return format!("{}.{}", path, index);
+}
+
+/// Writes the data to a temporary file and renames it over the file at the
+/// path, keeping the given number of backups of the previous versions. No
+/// backups are made when it is 0.
+pub fn write_atomically(path: &str, backups: usize, data: &str) -> io::Result<()> {
+    let temp_path = format!("{}.tmp", path);
+    let mut file = File::create(&temp_path)?;
+    file.write_all(data.as_bytes())?;
+    file.sync_all()?;
+    if backups > 0 && Path::new(path).exists() {
+        for index in (1..backups).rev() {
+            let from = backup_path(path, index);
+            if Path::new(&from).exists() {
+                fs::rename(&from, backup_path(path, index + 1))?;
+            }
+        }
+        // copied rather than moved, so there always is a session file
+        fs::copy(path, backup_path(path, 1))?;
+    }
+    fs::rename(&temp_path, path)?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn read(path: &str) -> String {
+        return fs::read_to_string(path).unwrap();
+    }
+
+    #[test]
+    fn backups_are_rotated_once_per_path() {
+        let dir = env::temp_dir().join(format!("padseq-autosave-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("session.json").to_str().unwrap().to_string();
+        fs::write(&path, "loaded").unwrap();
+        fs::write(backup_path(&path, 1), "older").unwrap();
+        write_atomically(&path, 2, "first").unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let writer = thread::spawn({
+            let path = path.clone();
+            move || run_writer(path, 2, receiver)
+        });
+        let mut session = Session::new(1);
+        for root in [1, 2, 3] {
+            session.set_root(root);
+            sender.send(Message::Save(session.clone())).unwrap();
+            let (done, wait) = mpsc::channel();
+            sender.send(Message::Flush(done)).unwrap();
+            wait.recv().unwrap();
+        }
+        drop(sender);
+        writer.join().unwrap();
+        assert_eq!(Session::from_json(&read(&path)).unwrap().get_root(), 3);
+        assert_eq!(read(&backup_path(&path, 1)), "first");
+        assert_eq!(read(&backup_path(&path, 2)), "loaded");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
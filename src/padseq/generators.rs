@@ -0,0 +1,250 @@
+use super::random::Random;
+use super::session::{Note, Pattern, Step, StepNotes, Velocity, BAR_SIZE};
+use serde::{Deserialize, Serialize};
+
+/// Everything a random pattern is generated from, stored with the pattern so
+/// it can be generated again.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RandomSettings {
+    pub seed: u64,
+    /// Percentage of steps which are set.
+    pub density: u8,
+    /// The notes are picked from these.
+    pub notes: Vec<Note>,
+    pub min_velocity: Velocity,
+    pub max_velocity: Velocity,
+    /// Applied in order after generating from the seed.
+    #[serde(default)]
+    pub mutations: Vec<Mutation>,
+}
+
+impl RandomSettings {
+    /// Checks the settings are within range, returns a description of every
+    /// problem found.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.density > 100 {
+            problems.push(format!("density {} is not within 0-100", self.density));
+        }
+        for note in &self.notes {
+            if *note > 127 {
+                problems.push(format!("note {} is not within 0-127", note));
+            }
+        }
+        for velocity in [self.min_velocity, self.max_velocity] {
+            if velocity > 127 {
+                problems.push(format!("velocity {} is not within 0-127", velocity));
+            }
+        }
+        if self.min_velocity > self.max_velocity {
+            problems.push(format!(
+                "the lowest velocity {} is above the highest {}",
+                self.min_velocity, self.max_velocity
+            ));
+        }
+        for (index, mutation) in self.mutations.iter().enumerate() {
+            if mutation.amount > 100 {
+                problems.push(format!(
+                    "mutation {}: amount {} is not within 0-100",
+                    index, mutation.amount
+                ));
+            }
+        }
+        return problems;
+    }
+}
+
+/// Generates a part of the steps again.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Mutation {
+    pub seed: u64,
+    /// Percentage of steps which are changed.
+    pub amount: u8,
+}
+
+/// Spreads `hits` as evenly as possible over `steps`, moved to the right by
+/// `rotation` steps. Returns whether each step is a hit.
+pub fn euclidean(hits: Step, steps: Step, rotation: Step) -> Vec<bool> {
+    let steps = steps.max(1) as usize;
+    let hits = (hits as usize).min(steps);
+    let rotation = rotation as usize % steps;
+    return (0..steps)
+        .map(|step| ((step + steps - rotation) * hits) % steps < hits)
+        .collect();
+}
+
+/// Replaces the notes in the pattern by the rhythm, which is repeated over
+/// the bar. Other notes are kept.
+pub fn fill_rhythm(
+    pattern: &Pattern,
+    notes: &[Note],
+    velocity: Velocity,
+    rhythm: &[bool],
+) -> Pattern {
+    let mut result = Pattern::new();
+    for step in 0..BAR_SIZE {
+        let mut step_notes = if pattern.has_step_set(step) {
+            pattern.get_step(step).clone()
+        } else {
+            StepNotes::new()
+        };
+        for note in notes {
+            step_notes.remove(note);
+        }
+        if !rhythm.is_empty() && rhythm[step as usize % rhythm.len()] {
+            for note in notes {
+                step_notes.insert(*note, velocity);
+            }
+        }
+        if !step_notes.is_empty() {
+            result.set_step(step, &step_notes);
+        }
+    }
+    return result;
+}
+
+fn random_step(settings: &RandomSettings, random: &mut Random) -> Option<StepNotes> {
+    if settings.notes.is_empty() || random.below(100) >= settings.density as u64 {
+        return None;
+    }
+    let note = settings.notes[random.below(settings.notes.len() as u64) as usize];
+    let velocity = random.range(settings.min_velocity as i32, settings.max_velocity as i32);
+    let mut step_notes = StepNotes::new();
+    step_notes.insert(note, velocity as Velocity);
+    return Some(step_notes);
+}
+
+/// Generates a pattern of single notes, the same settings always give the
+/// same pattern.
+pub fn random_pattern(settings: &RandomSettings) -> Pattern {
+    let mut random = Random::new(settings.seed);
+    let mut pattern = Pattern::new();
+    for step in 0..BAR_SIZE {
+        if let Some(step_notes) = random_step(settings, &mut random) {
+            pattern.set_step(step, &step_notes);
+        }
+    }
+    for mutation in &settings.mutations {
+        let mut random = Random::new(mutation.seed);
+        for step in 0..BAR_SIZE {
+            if random.below(100) < mutation.amount as u64 {
+                match random_step(settings, &mut random) {
+                    Some(step_notes) => pattern.set_step(step, &step_notes),
+                    None => pattern.clear_step(step),
+                }
+            }
+        }
+    }
+    pattern.set_random_settings(Some(settings.clone()));
+    return pattern;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> RandomSettings {
+        return RandomSettings {
+            seed: 42,
+            density: 50,
+            notes: vec![36, 38, 42],
+            min_velocity: 64,
+            max_velocity: 127,
+            mutations: vec![
+                Mutation {
+                    seed: 1,
+                    amount: 25,
+                },
+                Mutation {
+                    seed: 2,
+                    amount: 50,
+                },
+            ],
+        };
+    }
+
+    /// The notes of the pattern ordered by step and note.
+    fn steps(pattern: &Pattern) -> Vec<(Step, Note, Velocity)> {
+        let mut notes: Vec<(Step, Note, Velocity)> = pattern
+            .get_steps()
+            .iter()
+            .flat_map(|(step, notes)| notes.iter().map(move |(n, v)| (*step, *n, *v)))
+            .collect();
+        notes.sort();
+        return notes;
+    }
+
+    /// A rhythm written as `x` for a hit and `.` for a rest.
+    fn rhythm(text: &str) -> Vec<bool> {
+        return text.chars().map(|c| c == 'x').collect();
+    }
+
+    #[test]
+    fn euclidean_rhythms_spread_the_hits() {
+        assert_eq!(euclidean(3, 8, 0), rhythm("x..x..x."));
+        // the cinquillo "x.xx.xx.", started on another of its hits
+        assert_eq!(euclidean(5, 8, 0), rhythm("x.x.xx.x"));
+        assert_eq!(euclidean(5, 8, 6), rhythm("x.xx.xx."));
+        assert_eq!(euclidean(4, 16, 0), rhythm("x...x...x...x..."));
+    }
+
+    #[test]
+    fn euclidean_rotation_wraps_around() {
+        assert_eq!(euclidean(3, 8, 1), rhythm(".x..x..x"));
+        assert_eq!(euclidean(3, 8, 2), rhythm("x.x..x.."));
+        assert_eq!(euclidean(3, 8, 8), euclidean(3, 8, 0));
+        assert_eq!(euclidean(3, 8, 9), euclidean(3, 8, 1));
+    }
+
+    #[test]
+    fn euclidean_hits_are_limited_to_the_steps() {
+        assert_eq!(euclidean(0, 8, 3), rhythm("........"));
+        assert_eq!(euclidean(8, 8, 0), rhythm("xxxxxxxx"));
+        assert_eq!(euclidean(12, 8, 5), rhythm("xxxxxxxx"));
+        assert_eq!(euclidean(1, 0, 0), rhythm("x"));
+    }
+
+    #[test]
+    fn the_same_settings_give_the_same_pattern() {
+        let settings = settings();
+        let pattern = random_pattern(&settings);
+        assert!(!steps(&pattern).is_empty());
+        assert_eq!(steps(&pattern), steps(&random_pattern(&settings)));
+        // the stored settings generate it again
+        let stored = pattern.get_random_settings().unwrap();
+        assert_eq!(steps(&pattern), steps(&random_pattern(stored)));
+    }
+
+    #[test]
+    fn mutations_change_the_pattern() {
+        let mut unmutated = settings();
+        unmutated.mutations.clear();
+        assert_ne!(
+            steps(&random_pattern(&settings())),
+            steps(&random_pattern(&unmutated))
+        );
+    }
+
+    #[test]
+    fn generated_steps_follow_the_settings() {
+        let settings = settings();
+        let pattern = random_pattern(&settings);
+        assert_eq!(steps(&pattern).len(), pattern.get_steps().len());
+        for (_, note, velocity) in steps(&pattern) {
+            assert!(settings.notes.contains(&note));
+            assert!((64..=127).contains(&velocity));
+        }
+    }
+
+    #[test]
+    fn invalid_settings_are_reported() {
+        assert!(settings().validate().is_empty());
+        let mut invalid = settings();
+        invalid.density = 101;
+        invalid.notes.push(128);
+        invalid.min_velocity = 100;
+        invalid.max_velocity = 90;
+        invalid.mutations[1].amount = 200;
+        assert_eq!(invalid.validate().len(), 4);
+    }
+}
@@ -0,0 +1,199 @@
+use super::session::{Pattern, Session, Step, StepNotes};
+use std::collections::VecDeque;
+
+pub const DEFAULT_HISTORY_SIZE: usize = 100;
+
+/// A change of the session, holding the state before and after it so it can
+/// be undone and redone.
+pub enum Edit {
+    Step {
+        instrument: usize,
+        pattern: usize,
+        step: Step,
+        before: Option<StepNotes>,
+        after: Option<StepNotes>,
+    },
+    Pattern {
+        instrument: usize,
+        index: usize,
+        before: Option<Pattern>,
+        after: Option<Pattern>,
+    },
+    ActivePattern {
+        instrument: usize,
+        before: Option<usize>,
+        after: Option<usize>,
+    },
+}
+
+impl Edit {
+    /// Applies the edit to the session, or reverts it if `undo` is set.
+    fn apply(&self, session: &mut Session, undo: bool) {
+        // the instrument may have been removed since
+        if self.get_instrument() >= session.number_of_instruments() {
+            return;
+        }
+        match self {
+            Edit::Step {
+                instrument,
+                pattern,
+                step,
+                before,
+                after,
+            } => {
+                let notes = if undo { before } else { after };
+                if let Some(the_pattern) = session
+                    .get_instrument_mut(*instrument)
+                    .get_pattern_mut(*pattern)
+                {
+                    match notes {
+                        Some(notes) => the_pattern.set_step(*step, notes),
+                        None => the_pattern.clear_step(*step),
+                    }
+                }
+            }
+            Edit::Pattern {
+                instrument,
+                index,
+                before,
+                after,
+            } => {
+                let the_pattern = if undo { before } else { after };
+                let instrument = session.get_instrument_mut(*instrument);
+                match the_pattern {
+                    Some(the_pattern) => instrument.set_pattern(*index, the_pattern),
+                    None => instrument.remove_pattern(*index),
+                }
+            }
+            Edit::ActivePattern {
+                instrument,
+                before,
+                after,
+            } => {
+                let pattern = if undo { before } else { after };
+                session
+                    .get_instrument_mut(*instrument)
+                    .set_active_pattern(*pattern);
+            }
+        }
+    }
+
+    fn get_instrument(&self) -> usize {
+        match self {
+            Edit::Step { instrument, .. } => *instrument,
+            Edit::Pattern { instrument, .. } => *instrument,
+            Edit::ActivePattern { instrument, .. } => *instrument,
+        }
+    }
+}
+
+/// Applied edits which can be undone, and undone ones which can be redone.
+pub struct History {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    size: usize,
+}
+
+impl History {
+    pub fn new(size: usize) -> History {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            size: size,
+        }
+    }
+
+    /// Applies the edit and remembers it, dropping the oldest edit if the
+    /// history is full.
+    pub fn apply(&mut self, session: &mut Session, edit: Edit) {
+        edit.apply(session, false);
+        self.undo.push_back(edit);
+        if self.undo.len() > self.size {
+            self.undo.pop_front();
+        }
+        self.redo.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        return !self.undo.is_empty();
+    }
+
+    pub fn can_redo(&self) -> bool {
+        return !self.redo.is_empty();
+    }
+
+    /// Reverts the last edit, returns whether there was one.
+    pub fn undo(&mut self, session: &mut Session) -> bool {
+        match self.undo.pop_back() {
+            Some(edit) => {
+                edit.apply(session, true);
+                self.redo.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Applies the last undone edit again, returns whether there was one.
+    pub fn redo(&mut self, session: &mut Session) -> bool {
+        match self.redo.pop() {
+            Some(edit) => {
+                edit.apply(session, false);
+                self.undo.push_back(edit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets the edits of instruments from the given index on, so that
+    /// they aren't applied to instruments added later in their place.
+    pub fn forget_instruments(&mut self, number_of_instruments: usize) {
+        self.undo
+            .retain(|edit| edit.get_instrument() < number_of_instruments);
+        self.redo
+            .retain(|edit| edit.get_instrument() < number_of_instruments);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_step(instrument: usize, velocity: u8) -> Edit {
+        let mut notes = StepNotes::new();
+        notes.insert(36, velocity);
+        return Edit::Step {
+            instrument: instrument,
+            pattern: 0,
+            step: 0,
+            before: None,
+            after: Some(notes),
+        };
+    }
+
+    #[test]
+    fn edits_of_removed_instruments_are_forgotten() {
+        let mut session = Session::new(2);
+        session
+            .get_instrument_mut(1)
+            .set_pattern(0, &Pattern::new());
+        let mut history = History::new(DEFAULT_HISTORY_SIZE);
+        history.apply(&mut session, set_step(0, 100));
+        history.apply(&mut session, set_step(1, 90));
+        history.apply(&mut session, set_step(1, 80));
+        history.undo(&mut session);
+        session.remove_instrument();
+        history.forget_instruments(session.number_of_instruments());
+        session.add_instrument();
+        session
+            .get_instrument_mut(1)
+            .set_pattern(0, &Pattern::new());
+        // the new instrument keeps its empty pattern
+        assert!(!history.can_redo());
+        assert!(history.undo(&mut session));
+        assert!(!history.can_undo());
+        let new_pattern = session.get_instrument(1).get_pattern(0).unwrap();
+        assert!(!new_pattern.has_step_set(0));
+    }
+}
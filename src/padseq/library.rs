@@ -0,0 +1,162 @@
+use super::session::{Note, Pattern, Step, StepNotes, Velocity, BAR_SIZE, STEPS_PER_BEAT};
+use std::fs;
+use std::io;
+
+const KICK: Note = 36;
+const SNARE: Note = 38;
+const CLOSED_HAT: Note = 42;
+const OPEN_HAT: Note = 46;
+/// Steps of a four beat measure, patterns repeat their measure over the bar.
+const MEASURE: Step = 4 * STEPS_PER_BEAT;
+
+pub struct LibraryEntry {
+    name: String,
+    pattern: Pattern,
+    builtin: bool,
+}
+
+impl LibraryEntry {
+    pub fn get_name(&self) -> &str {
+        return &self.name;
+    }
+
+    pub fn get_pattern(&self) -> &Pattern {
+        return &self.pattern;
+    }
+
+    /// Whether the entry is one of the presets rather than a file.
+    pub fn is_builtin(&self) -> bool {
+        return self.builtin;
+    }
+}
+
+/// Patterns which can be pasted into any slot: the built-in presets followed
+/// by the pattern files of the library directory.
+pub struct PatternLibrary {
+    entries: Vec<LibraryEntry>,
+}
+
+impl PatternLibrary {
+    /// A library of the presets only.
+    pub fn new() -> PatternLibrary {
+        let entries = presets()
+            .into_iter()
+            .map(|(name, pattern)| LibraryEntry {
+                name: name.to_string(),
+                pattern,
+                builtin: true,
+            })
+            .collect();
+        return PatternLibrary { entries };
+    }
+
+    /// Adds the presets to the `.json` files of the directory, each holding a
+    /// single pattern.
+    pub fn load(directory: &str) -> io::Result<PatternLibrary> {
+        let mut library = PatternLibrary::new();
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|x| x == "json") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        for path in paths {
+            let pattern: Pattern = serde_json::from_str(&fs::read_to_string(&path)?)?;
+            let problems = pattern.validate();
+            if !problems.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), problems.join(", ")),
+                ));
+            }
+            library.entries.push(LibraryEntry {
+                name: path.file_stem().unwrap().to_string_lossy().into_owned(),
+                pattern,
+                builtin: false,
+            });
+        }
+        return Ok(library);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn get_entry(&self, index: usize) -> Option<&LibraryEntry> {
+        return self.entries.get(index);
+    }
+}
+
+/// Builds a pattern from the steps of one measure per note, repeated over
+/// the whole bar.
+fn measure(notes: &[(Note, Velocity, &[Step])]) -> Pattern {
+    let mut pattern = Pattern::new();
+    for start in (0..BAR_SIZE).step_by(MEASURE as usize) {
+        for (note, velocity, steps) in notes {
+            for step in steps.iter() {
+                let step = start + step;
+                let mut step_notes = if pattern.has_step_set(step) {
+                    pattern.get_step(step).clone()
+                } else {
+                    StepNotes::new()
+                };
+                step_notes.insert(*note, *velocity);
+                pattern.set_step(step, &step_notes);
+            }
+        }
+    }
+    return pattern;
+}
+
+/// Drum patterns using the General MIDI drum notes.
+fn presets() -> Vec<(&'static str, Pattern)> {
+    return vec![
+        ("four on the floor", measure(&[(KICK, 127, &[0, 4, 8, 12])])),
+        (
+            "four on the floor with hats",
+            measure(&[
+                (KICK, 127, &[0, 4, 8, 12]),
+                (SNARE, 110, &[4, 12]),
+                (OPEN_HAT, 90, &[2, 6, 10, 14]),
+            ]),
+        ),
+        (
+            "breakbeat",
+            measure(&[
+                (KICK, 127, &[0, 10]),
+                (SNARE, 120, &[4, 12]),
+                (CLOSED_HAT, 90, &[0, 2, 4, 6, 8, 10, 12, 14]),
+            ]),
+        ),
+        (
+            "syncopated breakbeat",
+            measure(&[
+                (KICK, 127, &[0, 6, 10]),
+                (SNARE, 120, &[4, 12]),
+                (SNARE, 50, &[7, 15]),
+                (CLOSED_HAT, 90, &[0, 2, 4, 6, 8, 10, 12, 14]),
+            ]),
+        ),
+        (
+            "eighth hats",
+            measure(&[
+                (CLOSED_HAT, 110, &[0, 4, 8, 12]),
+                (CLOSED_HAT, 70, &[2, 6, 10, 14]),
+            ]),
+        ),
+        (
+            "sixteenth hats",
+            measure(&[
+                (CLOSED_HAT, 110, &[0, 4, 8, 12]),
+                (CLOSED_HAT, 60, &[1, 3, 5, 7, 9, 11, 13, 15]),
+                (CLOSED_HAT, 85, &[2, 6, 10, 14]),
+            ]),
+        ),
+        (
+            "offbeat open hats",
+            measure(&[(OPEN_HAT, 100, &[2, 6, 10, 14])]),
+        ),
+    ];
+}
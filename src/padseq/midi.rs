@@ -1,24 +1,34 @@
 extern crate midir;
 
-use std::collections::{HashMap, VecDeque};
+use std::cell::RefCell;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::rc::Rc;
 use std::sync::mpsc;
 
 use std::time::{Duration, Instant};
 
 use super::session::{Channel, Note, Velocity};
 use midir::{Ignore, MidiIO, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use serde::{Deserialize, Serialize};
 
 pub struct MidiEvent {
     pub message: MidiMessage,
     pub instant: Option<Instant>,
 }
 
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub enum MidiMessageType {
     NoteOff,
     NoteOn,
+    PolyAftertouch,
     ControlChange,
+    ProgramChange,
+    ChannelAftertouch,
+    PitchBend,
 }
 
+#[derive(Clone)]
 pub struct MidiMessage {
     pub r#type: MidiMessageType,
     pub channel: Channel,
@@ -27,58 +37,220 @@ pub struct MidiMessage {
 }
 
 impl MidiMessage {
-    pub fn to_array(&self) -> [u8; 3] {
+    pub fn to_array(&self) -> Vec<u8> {
         let the_type = match self.r#type {
-            MidiMessageType::NoteOff => 0x80 + self.channel - 1,
-            MidiMessageType::NoteOn => 0x90 + self.channel - 1,
-            MidiMessageType::ControlChange => 0xB0 + self.channel - 1,
+            MidiMessageType::NoteOff => 0x80,
+            MidiMessageType::NoteOn => 0x90,
+            MidiMessageType::PolyAftertouch => 0xA0,
+            MidiMessageType::ControlChange => 0xB0,
+            MidiMessageType::ProgramChange => 0xC0,
+            MidiMessageType::ChannelAftertouch => 0xD0,
+            MidiMessageType::PitchBend => 0xE0,
+        } + self.channel
+            - 1;
+        return match self.r#type {
+            MidiMessageType::ProgramChange | MidiMessageType::ChannelAftertouch => {
+                vec![the_type, self.note]
+            }
+            _ => vec![the_type, self.note, self.velocity],
         };
-        return [the_type, self.note, self.velocity];
     }
 
-    pub fn from_array(message: &[u8]) -> MidiMessage {
+    /// Parses a channel message, returns `None` for system messages.
+    pub fn from_array(message: &[u8]) -> Option<MidiMessage> {
         println!("received {:?}", message);
-        return MidiMessage {
-            r#type: match message[0] {
-                0x80..=0x8F => MidiMessageType::NoteOff,
-                0x90..=0x9F => MidiMessageType::NoteOn,
-                0xb0..=0xbf => MidiMessageType::ControlChange,
-                _ => panic!("Unknown MIDI message {:?}", message), // TODO
-            },
-            channel: 1, // TODO
-            note: message[1],
-            velocity: message[2],
+        let r#type = match message.first()? {
+            0x80..=0x8F => MidiMessageType::NoteOff,
+            0x90..=0x9F => MidiMessageType::NoteOn,
+            0xA0..=0xAF => MidiMessageType::PolyAftertouch,
+            0xB0..=0xBF => MidiMessageType::ControlChange,
+            0xC0..=0xCF => MidiMessageType::ProgramChange,
+            0xD0..=0xDF => MidiMessageType::ChannelAftertouch,
+            0xE0..=0xEF => MidiMessageType::PitchBend,
+            _ => return None,
         };
+        return Some(MidiMessage {
+            r#type: r#type,
+            channel: (message[0] & 0x0F) + 1,
+            note: *message.get(1)?,
+            velocity: message.get(2).copied().unwrap_or(0),
+        });
     }
 }
 
 type MidiEventQueue = VecDeque<MidiEvent>;
 
+struct ScheduledEvent {
+    instant: Instant,
+    // keeps events due at the same instant in the order they were scheduled
+    sequence: u64,
+    output: usize,
+    message: MidiMessage,
+}
+
+impl ScheduledEvent {
+    fn key(&self) -> (Instant, u64) {
+        (self.instant, self.sequence)
+    }
+}
+
+impl PartialEq for ScheduledEvent {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for ScheduledEvent {}
+
+impl PartialOrd for ScheduledEvent {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScheduledEvent {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Outgoing events of all outputs, ordered by the time they are due.
+pub struct EventScheduler {
+    events: BinaryHeap<Reverse<ScheduledEvent>>,
+    outputs: Vec<Option<MidiOutputConnection>>,
+    // outputs of dropped instruments, reused by the next ones
+    free_outputs: Vec<usize>,
+    sequence: u64,
+    // sequence numbers of events which are dropped when they are due
+    cancelled: HashSet<u64>,
+}
+
+pub type SharedScheduler = Rc<RefCell<EventScheduler>>;
+
+impl EventScheduler {
+    pub fn new() -> EventScheduler {
+        EventScheduler {
+            events: BinaryHeap::new(),
+            outputs: Vec::new(),
+            free_outputs: Vec::new(),
+            sequence: 0,
+            cancelled: HashSet::new(),
+        }
+    }
+
+    pub fn new_shared() -> SharedScheduler {
+        Rc::new(RefCell::new(EventScheduler::new()))
+    }
+
+    fn add_output(&mut self) -> usize {
+        if let Some(output) = self.free_outputs.pop() {
+            return output;
+        }
+        self.outputs.push(None);
+        return self.outputs.len() - 1;
+    }
+
+    /// Closes the connection of the output and drops the events still waiting
+    /// for it, so that the next output can take its place.
+    fn remove_output(&mut self, output: usize) {
+        self.outputs[output] = None;
+        let cancelled = &mut self.cancelled;
+        self.events.retain(|Reverse(event)| {
+            if event.output == output {
+                cancelled.remove(&event.sequence);
+                return false;
+            }
+            return true;
+        });
+        self.free_outputs.push(output);
+    }
+
+    fn set_connection(&mut self, output: usize, connection: MidiOutputConnection) {
+        self.outputs[output] = Some(connection);
+    }
+
+    /// Returns the sequence number which cancels the event.
+    fn schedule(&mut self, output: usize, message: MidiMessage, instant: Instant) -> u64 {
+        self.sequence += 1;
+        self.events.push(Reverse(ScheduledEvent {
+            instant: instant,
+            sequence: self.sequence,
+            output: output,
+            message: message,
+        }));
+        return self.sequence;
+    }
+
+    /// Drops an event which is not due yet.
+    fn cancel(&mut self, sequence: u64) {
+        self.cancelled.insert(sequence);
+    }
+
+    /// Sends all events which are due, on whichever output they belong to.
+    pub fn send_due(&mut self) {
+        let now = Instant::now();
+        while let Some(Reverse(event)) = self.events.peek() {
+            if event.instant > now {
+                break;
+            }
+            let Reverse(event) = self.events.pop().unwrap();
+            if self.cancelled.remove(&event.sequence) {
+                continue;
+            }
+            if let Some(out) = &mut self.outputs[event.output] {
+                let _ = out.send(&event.message.to_array());
+            }
+        }
+    }
+}
+
+/// A sounding note, identified by its channel and note number.
+type Voice = (Channel, Note);
+
+/// The note-off of a sounding note, waiting in the scheduler.
+struct PendingStop {
+    instant: Instant,
+    sequence: u64,
+}
+
 pub struct Instrument {
     name: String,
-    midi_out: Option<MidiOutputConnection>,
+    scheduler: SharedScheduler,
+    output: usize,
     midi_in: Option<MidiInputConnection<mpsc::Sender<MidiEvent>>>,
     events_in: MidiEventQueue,
-    events_out: MidiEventQueue,
     chan_out: mpsc::Sender<MidiEvent>,
     chan_in: mpsc::Receiver<MidiEvent>,
     debug: bool,
-    stop_notes: HashMap<Note, Instant>,
+    buttons_only: bool,
+    stop_notes: HashMap<Voice, PendingStop>,
+    used_channels: HashSet<Channel>,
+    held_controls: HashSet<Note>,
 }
 
 impl Instrument {
     pub fn new(name: &str) -> Instrument {
+        return Instrument::with_scheduler(name, EventScheduler::new_shared());
+    }
+
+    /// Creates an instrument whose outgoing events are sent through the
+    /// given scheduler, in order with those of the other instruments.
+    pub fn with_scheduler(name: &str, scheduler: SharedScheduler) -> Instrument {
         let (tx, rx) = mpsc::channel();
+        let output = scheduler.borrow_mut().add_output();
         Instrument {
-            midi_out: None,
+            scheduler: scheduler,
+            output: output,
             midi_in: None,
             events_in: VecDeque::new(),
-            events_out: VecDeque::new(),
             chan_in: rx,
             chan_out: tx,
             name: name.to_string(),
             debug: false,
+            buttons_only: false,
             stop_notes: HashMap::new(),
+            used_channels: HashSet::new(),
+            held_controls: HashSet::new(),
         }
     }
 
@@ -86,67 +258,78 @@ impl Instrument {
         self.debug = v;
     }
 
-    fn enqueue_stop_notes(&mut self) {
-        for (note, instant) in &self.stop_notes.clone() {
-            if Instant::now() > *instant {
-                self.play_note(1, *note, 0, 0.0);
-                self.stop_notes.remove(&note);
-            }
-        }
+    /// Only receives the messages of pads and buttons, notes and control
+    /// changes. Pressure, program changes and pitch bends are dropped.
+    pub fn set_buttons_only(&mut self, v: bool) {
+        self.buttons_only = v;
+    }
+
+    /// Forgets the notes whose note-off is due, the scheduler sends them.
+    fn forget_stopped_notes(&mut self) {
+        let now = Instant::now();
+        self.stop_notes.retain(|_, stop| stop.instant > now);
+    }
+
+    /// Whether the note has a note-off waiting to be sent.
+    fn is_sounding(&self, voice: &Voice) -> bool {
+        return self
+            .stop_notes
+            .get(voice)
+            .is_some_and(|stop| stop.instant > Instant::now());
     }
 
     pub fn send_events(&mut self) {
-        self.enqueue_stop_notes();
-        for _ in 0..self.events_out.len() {
-            let message = self.events_out.pop_front();
-            match message {
-                Some(x) => {
-                    if x.instant == None || Instant::now() > x.instant.unwrap() {
-                        match &mut self.midi_out {
-                            Some(out) => {
-                                if self.debug {
-                                    println!("send {:?}", &x.message.to_array());
-                                }
-                                let _ = out.send(&x.message.to_array());
-                            }
-                            None => {}
-                        }
-                    } else {
-                        self.events_out.insert(0, x);
-                    }
-                }
-                None => {}
-            }
-        }
+        self.forget_stopped_notes();
+        self.scheduler.borrow_mut().send_due();
     }
 
+    /// Plays a note, which is stopped after `duration` milliseconds unless
+    /// the duration is zero. The note-off is scheduled right away at its due
+    /// time. A note that is still sounding on the same channel is stopped
+    /// before it is played again.
     pub fn play_note(&mut self, channel: Channel, note: Note, velocity: Velocity, duration: f64) {
-        {
-            if duration == 0.0 || !self.stop_notes.contains_key(&note) {
-                if self.debug {
-                    println!("play on note {} {} {}", note, velocity, duration);
-                }
-                let message = MidiMessage {
-                    r#type: MidiMessageType::NoteOn,
-                    note: note,
-                    velocity: velocity,
-                    channel: channel,
-                };
-                self.push_event(MidiEvent {
-                    message: message,
-                    instant: None,
-                });
+        if self.is_sounding(&(channel, note)) {
+            if self.debug {
+                println!("retrigger note {} on channel {}", note, channel);
             }
+            self.stop_note(channel, note);
+        }
+        if self.debug {
+            println!("play on note {} {} {}", note, velocity, duration);
         }
+        let message = MidiMessage {
+            r#type: MidiMessageType::NoteOn,
+            note: note,
+            velocity: velocity,
+            channel: channel,
+        };
+        self.push_event(MidiEvent {
+            message: message,
+            instant: None,
+        });
         if duration > 0.0 {
             if self.debug {
                 println!("also play stop note");
             }
+            let instant = Instant::now()
+                .checked_add(Duration::from_secs_f64(duration / 1000.0))
+                .unwrap();
+            let message = MidiMessage {
+                r#type: MidiMessageType::NoteOff,
+                note: note,
+                velocity: 0,
+                channel: channel,
+            };
+            let sequence = self.push_event(MidiEvent {
+                message: message,
+                instant: Some(instant),
+            });
             self.stop_notes.insert(
-                note,
-                Instant::now()
-                    .checked_add(Duration::from_secs_f64(duration / 1000.0))
-                    .unwrap(),
+                (channel, note),
+                PendingStop {
+                    instant: instant,
+                    sequence: sequence,
+                },
             );
         }
     }
@@ -164,7 +347,20 @@ impl Instrument {
         });
     }
 
+    pub fn send_message(&mut self, message: MidiMessage) {
+        self.push_event(MidiEvent {
+            message: message,
+            instant: None,
+        });
+    }
+
+    /// Stops a note now, its scheduled note-off is dropped.
     pub fn stop_note(&mut self, channel: Channel, note: Note) {
+        if let Some(stop) = self.stop_notes.remove(&(channel, note)) {
+            if stop.instant > Instant::now() {
+                self.scheduler.borrow_mut().cancel(stop.sequence);
+            }
+        }
         if self.debug {
             println!("stop note {}", note);
         }
@@ -180,10 +376,46 @@ impl Instrument {
         });
     }
 
+    /// Releases all sounding notes and sends all notes off and all sound off
+    /// on every channel used so far.
+    pub fn panic(&mut self) {
+        self.forget_stopped_notes();
+        let mut voices: Vec<Voice> = self.stop_notes.keys().copied().collect();
+        voices.sort();
+        for (channel, note) in voices {
+            self.stop_note(channel, note);
+        }
+        let mut channels: Vec<Channel> = self.used_channels.iter().copied().collect();
+        channels.sort();
+        for channel in channels {
+            self.send_cc(channel, 123, 0);
+            self.send_cc(channel, 120, 0);
+        }
+        self.send_events();
+    }
+
     fn receive_events(&mut self) {
         loop {
             match self.chan_in.try_recv() {
-                Ok(t) => self.events_in.push_back(t),
+                Ok(t) => {
+                    let is_button = matches!(
+                        t.message.r#type,
+                        MidiMessageType::NoteOn
+                            | MidiMessageType::NoteOff
+                            | MidiMessageType::ControlChange
+                    );
+                    if self.buttons_only && !is_button {
+                        continue;
+                    }
+                    if t.message.r#type == MidiMessageType::ControlChange {
+                        if t.message.velocity > 0 {
+                            self.held_controls.insert(t.message.note);
+                        } else {
+                            self.held_controls.remove(&t.message.note);
+                        }
+                    }
+                    self.events_in.push_back(t)
+                }
                 Err(e) => match e {
                     mpsc::TryRecvError::Empty => break,
                     mpsc::TryRecvError::Disconnected => panic!("Channel died"),
@@ -194,7 +426,7 @@ impl Instrument {
 
     pub fn has_events(&mut self) -> bool {
         self.receive_events();
-        return self.events_in.len() > 0;
+        return !self.events_in.is_empty();
     }
 
     pub fn pop_event(&mut self) -> Option<MidiEvent> {
@@ -203,8 +435,24 @@ impl Instrument {
         return element;
     }
 
-    fn push_event(&mut self, event: MidiEvent) {
-        return self.events_out.push_back(event);
+    /// Whether the button sending the given control change is held down.
+    pub fn is_control_held(&mut self, cc: Note) -> bool {
+        self.receive_events();
+        return self.held_controls.contains(&cc);
+    }
+
+    /// Schedules the event, now if it has no instant. Returns the sequence
+    /// number which cancels it.
+    fn push_event(&mut self, event: MidiEvent) -> u64 {
+        if self.debug {
+            println!("schedule {:?}", &event.message.to_array());
+        }
+        self.used_channels.insert(event.message.channel);
+        return self.scheduler.borrow_mut().schedule(
+            self.output,
+            event.message,
+            event.instant.unwrap_or_else(Instant::now),
+        );
     }
 
     pub fn connect_out(&mut self, port: u8) {
@@ -213,7 +461,9 @@ impl Instrument {
         let port_name = midi_out.port_name(&out_port).unwrap();
         println!("Connection open, outgoing to '{}' ...", port_name);
         let conn_out = midi_out.connect(&out_port, &self.name).unwrap();
-        self.midi_out = Some(conn_out);
+        self.scheduler
+            .borrow_mut()
+            .set_connection(self.output, conn_out);
     }
 
     pub fn connect_in(&mut self, port: u8) {
@@ -233,12 +483,14 @@ impl Instrument {
                         // conn_out.send(message).unwrap_or_else(|_| println!("Error when forwarding message ..."));
                         println!("{}: {:?} (len = {})", stamp, message, message.len());
                         // let value : usize = message[1] as usize;
-                        chan_out
-                            .send(MidiEvent {
-                                message: MidiMessage::from_array(message),
-                                instant: None,
-                            })
-                            .unwrap();
+                        if let Some(message) = MidiMessage::from_array(message) {
+                            chan_out
+                                .send(MidiEvent {
+                                    message: message,
+                                    instant: None,
+                                })
+                                .unwrap();
+                        }
                     },
                     self.chan_out.clone(),
                 )
@@ -265,3 +517,134 @@ impl Instrument {
         Ok(port.clone())
     }
 }
+
+impl Drop for Instrument {
+    // sends the note-offs of a panic before the output goes away
+    fn drop(&mut self) {
+        self.send_events();
+        self.scheduler.borrow_mut().remove_output(self.output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The scheduled messages which weren't cancelled, in the order they are
+    /// sent, with their delay after the first one.
+    fn scheduled(instrument: &Instrument) -> Vec<(MidiMessageType, Channel, Note, Duration)> {
+        let scheduler = instrument.scheduler.borrow();
+        let mut events: Vec<&ScheduledEvent> = scheduler
+            .events
+            .iter()
+            .map(|x| &x.0)
+            .filter(|x| !scheduler.cancelled.contains(&x.sequence))
+            .collect();
+        events.sort_by_key(|x| x.key());
+        let start = events.first().map(|x| x.instant);
+        return events
+            .iter()
+            .map(|x| {
+                (
+                    x.message.r#type,
+                    x.message.channel,
+                    x.message.note,
+                    x.instant - start.unwrap(),
+                )
+            })
+            .collect();
+    }
+
+    fn messages(instrument: &Instrument) -> Vec<(MidiMessageType, Channel, Note)> {
+        return scheduled(instrument)
+            .into_iter()
+            .map(|(r#type, channel, note, _)| (r#type, channel, note))
+            .collect();
+    }
+
+    #[test]
+    fn same_note_on_two_channels_is_tracked_separately() {
+        let mut instrument = Instrument::new("test");
+        instrument.play_note(1, 60, 100, 1000.0);
+        instrument.play_note(2, 60, 100, 1000.0);
+        assert!(instrument.is_sounding(&(1, 60)));
+        assert!(instrument.is_sounding(&(2, 60)));
+        instrument.stop_note(1, 60);
+        assert!(!instrument.is_sounding(&(1, 60)));
+        assert!(instrument.is_sounding(&(2, 60)));
+        assert_eq!(
+            messages(&instrument),
+            vec![
+                (MidiMessageType::NoteOn, 1, 60),
+                (MidiMessageType::NoteOn, 2, 60),
+                (MidiMessageType::NoteOff, 1, 60),
+                (MidiMessageType::NoteOff, 2, 60),
+            ]
+        );
+    }
+
+    #[test]
+    fn retrigger_stops_the_note_before_playing_it() {
+        let mut instrument = Instrument::new("test");
+        instrument.play_note(1, 60, 100, 1000.0);
+        instrument.play_note(1, 60, 90, 1000.0);
+        // the first note-off is replaced by the one of the second note
+        assert_eq!(
+            messages(&instrument),
+            vec![
+                (MidiMessageType::NoteOn, 1, 60),
+                (MidiMessageType::NoteOff, 1, 60),
+                (MidiMessageType::NoteOn, 1, 60),
+                (MidiMessageType::NoteOff, 1, 60),
+            ]
+        );
+    }
+
+    #[test]
+    fn note_offs_are_scheduled_at_their_due_time_on_their_channel() {
+        let mut instrument = Instrument::new("test");
+        instrument.play_note(3, 60, 100, 100.0);
+        instrument.play_note(5, 62, 100, 1000.0);
+        let events = scheduled(&instrument);
+        assert_eq!(events.len(), 4);
+        let (r#type, channel, note, delay) = events[2];
+        assert_eq!((r#type, channel, note), (MidiMessageType::NoteOff, 3, 60));
+        assert!(delay >= Duration::from_millis(100) && delay < Duration::from_millis(200));
+        let (r#type, channel, note, delay) = events[3];
+        assert_eq!((r#type, channel, note), (MidiMessageType::NoteOff, 5, 62));
+        assert!(delay >= Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn cancelled_events_are_not_sent() {
+        let mut scheduler = EventScheduler::new();
+        let output = scheduler.add_output();
+        let message = MidiMessage {
+            r#type: MidiMessageType::NoteOff,
+            channel: 1,
+            note: 60,
+            velocity: 0,
+        };
+        let sequence = scheduler.schedule(output, message, Instant::now());
+        scheduler.cancel(sequence);
+        scheduler.send_due();
+        assert!(scheduler.events.is_empty());
+        assert!(scheduler.cancelled.is_empty());
+    }
+
+    #[test]
+    fn dropped_instruments_give_back_their_output() {
+        let scheduler = EventScheduler::new_shared();
+        let mut first = Instrument::with_scheduler("first", scheduler.clone());
+        let second = Instrument::with_scheduler("second", scheduler.clone());
+        first.play_note(1, 60, 100, 1000.0);
+        let output = first.output;
+        drop(first);
+        // the pending note-off of the dropped instrument is gone
+        assert!(scheduler.borrow().events.is_empty());
+        let third = Instrument::with_scheduler("third", scheduler.clone());
+        assert_eq!(third.output, output);
+        assert_ne!(third.output, second.output);
+        assert_eq!(scheduler.borrow().outputs.len(), 2);
+    }
+}
@@ -0,0 +1,67 @@
+use super::session::Session;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// File a new project is saved to when the directory has no sessions yet.
+const NEW_PROJECT_FILE: &str = "session.json";
+
+/// The session files of a project directory, sorted by name.
+#[derive(Clone)]
+pub struct ProjectLibrary {
+    files: Vec<String>,
+}
+
+impl ProjectLibrary {
+    /// Scans the directory for `.json` files.
+    pub fn scan(directory: &str) -> io::Result<ProjectLibrary> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|x| x == "json") {
+                files.push(path.to_string_lossy().into_owned());
+            }
+        }
+        files.sort();
+        if files.is_empty() {
+            let path = Path::new(directory).join(NEW_PROJECT_FILE);
+            files.push(path.to_string_lossy().into_owned());
+        }
+        return Ok(ProjectLibrary { files });
+    }
+
+    pub fn len(&self) -> usize {
+        return self.files.len();
+    }
+
+    pub fn get_path(&self, index: usize) -> Option<&str> {
+        return self.files.get(index).map(|x| x.as_str());
+    }
+
+    /// The name of the project, its file name without the extension.
+    pub fn get_name(&self, index: usize) -> Option<String> {
+        let path = Path::new(self.files.get(index)?);
+        return Some(path.file_stem()?.to_string_lossy().into_owned());
+    }
+
+    /// Reads the session of the project, a new one if the file doesn't exist
+    /// yet.
+    pub fn load(&self, index: usize, number_of_instruments: usize) -> io::Result<Session> {
+        let path = match self.files.get(index) {
+            Some(path) => path,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no such project")),
+        };
+        if !Path::new(path).exists() {
+            return Ok(Session::new(number_of_instruments));
+        }
+        let session = Session::from_json(&fs::read_to_string(path)?)?;
+        let problems = session.validate();
+        if !problems.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                problems.join(", "),
+            ));
+        }
+        return Ok(session);
+    }
+}
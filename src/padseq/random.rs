@@ -0,0 +1,48 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A small xorshift generator, the same seed always gives the same numbers.
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        let state = seed ^ 0x9e37_79b9_7f4a_7c15;
+        // the state must never be zero
+        Random {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    /// A generator seeded from the clock.
+    pub fn from_time() -> Random {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_nanos() as u64)
+            .unwrap_or(0);
+        return Random::new(nanos);
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        return self.state;
+    }
+
+    /// A number from `0` up to but excluding `bound`.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return 0;
+        }
+        return self.next_u64() % bound;
+    }
+
+    /// A number from `min` to `max`, both included.
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        if max <= min {
+            return min;
+        }
+        return min + self.below((max - min + 1) as u64) as i32;
+    }
+}
@@ -0,0 +1,106 @@
+use super::session::Note;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum Scale {
+    Major,
+    Minor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues,
+    Chromatic,
+    /// Semitones above the root, ascending and below 12.
+    Custom(Vec<u8>),
+}
+
+impl Scale {
+    /// Every scale but the custom one.
+    pub const PRESETS: [Scale; 11] = [
+        Scale::Major,
+        Scale::Minor,
+        Scale::Dorian,
+        Scale::Phrygian,
+        Scale::Lydian,
+        Scale::Mixolydian,
+        Scale::Locrian,
+        Scale::MajorPentatonic,
+        Scale::MinorPentatonic,
+        Scale::Blues,
+        Scale::Chromatic,
+    ];
+
+    /// Semitones of the notes of the scale above the root.
+    pub fn get_intervals(&self) -> &[u8] {
+        match self {
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Scale::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            Scale::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            Scale::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            Scale::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            Scale::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Scale::MinorPentatonic => &[0, 3, 5, 7, 10],
+            Scale::Blues => &[0, 3, 5, 6, 7, 10],
+            Scale::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            Scale::Custom(intervals) => intervals,
+        }
+    }
+
+    /// The notes of the scale over one octave from the root on.
+    pub fn get_notes(&self, root: Note) -> Vec<Note> {
+        return self
+            .get_intervals()
+            .iter()
+            .map(|x| root as u16 + *x as u16)
+            .filter(|x| *x <= 127)
+            .map(|x| x as Note)
+            .collect();
+    }
+
+    /// The note of the given degree counted from the root, continuing into
+    /// the octaves above. `None` beyond the MIDI range.
+    pub fn get_note(&self, root: Note, degree: usize) -> Option<Note> {
+        let intervals = self.get_intervals();
+        if intervals.is_empty() {
+            return None;
+        }
+        let octave = degree / intervals.len();
+        let note = root as usize + octave * 12 + intervals[degree % intervals.len()] as usize;
+        return if note <= 127 {
+            Some(note as Note)
+        } else {
+            None
+        };
+    }
+
+    /// Whether the degree falls on the root of an octave.
+    pub fn is_root(&self, degree: usize) -> bool {
+        return degree.is_multiple_of(self.get_intervals().len().max(1));
+    }
+
+    /// Checks the intervals of a custom scale, returns a description of every
+    /// problem found.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let intervals = self.get_intervals();
+        if intervals.is_empty() {
+            problems.push("the scale has no notes".to_string());
+        }
+        if intervals.first().is_some_and(|x| *x != 0) {
+            problems.push("the scale doesn't start at the root".to_string());
+        }
+        if intervals.iter().any(|x| *x >= 12) {
+            problems.push("the scale has intervals beyond the octave".to_string());
+        }
+        if intervals.windows(2).any(|x| x[0] >= x[1]) {
+            problems.push("the intervals of the scale aren't ascending".to_string());
+        }
+        return problems;
+    }
+}
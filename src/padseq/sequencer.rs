@@ -1,14 +1,20 @@
-use super::midi::Instrument;
-use super::session::{Note, Session, Step, BAR_SIZE};
-use std::fs;
-use std::path::Path;
+use super::autosave::{Autosave, DEFAULT_BACKUPS};
+use super::history::{Edit, History, DEFAULT_HISTORY_SIZE};
+use super::library::PatternLibrary;
+use super::midi::{EventScheduler, Instrument, MidiMessageType, SharedScheduler};
+use super::project::ProjectLibrary;
+use super::session::{
+    Channel, LaunchQuantization, Note, Pattern, Scene, Session, Step, StepNotes, Velocity, BAR_SIZE,
+};
+use super::thru::{ThruConfig, ThruRouter};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
 use std::time::Instant;
 
-pub const NUMBER_OF_INSTRUMENTS: usize = 8;
-
-type StepSize = f64;
-const BASE_BPM: StepSize = 126.0;
-const STEP_LENGTH: StepSize = 1000.0 * 60.0 / (4.0 * BASE_BPM);
+/// Number of instruments of a new session.
+pub const DEFAULT_NUMBER_OF_INSTRUMENTS: usize = 8;
 
 pub enum WaitResult {
     Step,
@@ -17,92 +23,487 @@ pub enum WaitResult {
 
 pub type PlayedNotes = Vec<(usize, Note)>;
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum RecordMode {
+    /// Recorded notes are added to the existing ones.
+    Overdub,
+    /// Steps are cleared as the playhead passes them on the first pass.
+    Replace,
+}
+
+/// The previous session after a project switch, which keeps playing while it
+/// fades out over the first bar of the next one.
+struct FadeOut {
+    session: Session,
+    // instruments which were removed or changed their port with the switch
+    instruments: HashMap<usize, Instrument>,
+}
+
+/// Scales the velocity of a note played during the bar after a project
+/// switch, the next session fades in while the previous one fades out.
+/// Returns `None` for notes which became silent.
+fn fade_velocity(velocity: Velocity, step: Step, fading_in: bool) -> Option<Velocity> {
+    let gain = if fading_in {
+        step as u32 + 1
+    } else {
+        (BAR_SIZE - step) as u32
+    };
+    let velocity = (velocity as u32 * gain / BAR_SIZE as u32) as Velocity;
+    return if velocity > 0 { Some(velocity) } else { None };
+}
+
+struct SongPosition {
+    scene: usize,
+    bars_played: u8,
+    // the scene starts at the next bar
+    pending: bool,
+}
+
 pub struct Sequencer {
     session: Session,
     instruments: Vec<Instrument>,
-    session_file_path: Option<String>,
+    scheduler: SharedScheduler,
+    autosave: Option<Autosave>,
     active_step: Step,
     last_step: Instant,
+    keyboard_port: Option<u8>,
+    keyboard: Option<Instrument>,
+    // the instrument and channel each held key of the keyboard plays on
+    held_keyboard_notes: HashMap<Note, (usize, Channel)>,
+    thru: Option<ThruRouter>,
+    selected: Option<(usize, usize)>,
+    recording: bool,
+    record_mode: RecordMode,
+    replaced_steps: HashSet<Step>,
+    queued_patterns: HashMap<usize, (Option<usize>, LaunchQuantization)>,
+    song_position: Option<SongPosition>,
+    history: History,
+    projects: Option<ProjectLibrary>,
+    project: usize,
+    // loaded in the background so the pad press doesn't wait on the disk
+    queued_project: Option<(usize, Receiver<io::Result<Session>>)>,
+    fade_out: Option<FadeOut>,
+    pattern_library: PatternLibrary,
+    // played instead of the pattern of the session while it is generated
+    preview: Option<(usize, usize, Pattern)>,
 }
 
 impl Sequencer {
-    pub fn new(file_path: Option<String>) -> Sequencer {
-        let session = match file_path {
-            Some(ref path) => match Path::new(path).exists() {
-                true => Session::from_json(
-                    &fs::read_to_string(path).expect("Should have been able to read the file"),
-                )
-                .unwrap(),
-                false => Session::new(NUMBER_OF_INSTRUMENTS),
-            },
-            None => Session::new(NUMBER_OF_INSTRUMENTS),
-        };
+    /// Creates a sequencer playing the session, which is saved to the file
+    /// if one is given.
+    pub fn new(session: Session, file_path: Option<String>) -> Sequencer {
         Sequencer {
             session,
-            session_file_path: file_path.clone(),
+            autosave: file_path
+                .as_ref()
+                .map(|x| Autosave::new(x, DEFAULT_BACKUPS)),
             instruments: Vec::new(),
+            scheduler: EventScheduler::new_shared(),
             active_step: 0,
             last_step: Instant::now(),
+            keyboard_port: None,
+            keyboard: None,
+            held_keyboard_notes: HashMap::new(),
+            thru: None,
+            selected: None,
+            recording: false,
+            record_mode: RecordMode::Overdub,
+            replaced_steps: HashSet::new(),
+            queued_patterns: HashMap::new(),
+            song_position: None,
+            history: History::new(DEFAULT_HISTORY_SIZE),
+            projects: None,
+            project: 0,
+            queued_project: None,
+            fade_out: None,
+            pattern_library: PatternLibrary::new(),
+            preview: None,
         }
     }
 
+    /// Lets the sessions of the project directory be switched to, `project`
+    /// being the one currently played.
+    pub fn set_projects(&mut self, projects: ProjectLibrary, project: usize) {
+        self.projects = Some(projects);
+        self.project = project;
+    }
+
+    /// Sets the input port of the keyboard used for recording, must be
+    /// called before `connect`.
+    pub fn set_keyboard_port(&mut self, port: Option<u8>) {
+        self.keyboard_port = port;
+    }
+
+    /// Sets up MIDI thru to the selected instrument, must be called before
+    /// `connect`.
+    pub fn set_thru(&mut self, config: ThruConfig) {
+        self.thru = Some(ThruRouter::new(config));
+    }
+
+    fn create_instrument(&self, index: usize) -> Instrument {
+        let name: String = format!("instrument {}", index);
+        let mut instrument = Instrument::with_scheduler(&name, self.scheduler.clone());
+        instrument.set_debug(true);
+        instrument.connect_out(self.session.get_instrument(index).get_port());
+        return instrument;
+    }
+
+    fn connect_instrument(&mut self, index: usize) {
+        let instrument = self.create_instrument(index);
+        self.instruments.push(instrument);
+    }
+
     pub fn connect(&mut self) {
-        for n in 0..NUMBER_OF_INSTRUMENTS {
-            let name: String = format!("instrument {}", n);
-            let mut instrument = Instrument::new(&name);
-            instrument.set_debug(true);
-            instrument.connect_out(0);
-            self.instruments.push(instrument);
+        for n in 0..self.session.number_of_instruments() {
+            self.connect_instrument(n);
+        }
+        if let Some(port) = self.keyboard_port {
+            let mut keyboard = Instrument::new("keyboard");
+            keyboard.connect_in(port);
+            self.keyboard = Some(keyboard);
+        }
+        if let Some(thru) = &mut self.thru {
+            thru.connect();
         }
         print!("Connect done");
     }
 
-    fn play_notes(&mut self) -> PlayedNotes {
-        let mut played_notes = PlayedNotes::new();
-        for instrument in 0..NUMBER_OF_INSTRUMENTS {
-            match self.session.get_instrument(instrument).get_active_pattern() {
-                Some(pattern) => {
-                    if self.session.get_instrument(instrument).has_pattern(pattern)
-                        && self
-                            .session
-                            .get_instrument(instrument)
-                            .get_pattern(pattern)
-                            .unwrap()
-                            .has_step_set(self.active_step)
+    /// Appends a new instrument to the session and connects it.
+    pub fn add_instrument(&mut self) {
+        self.session.add_instrument();
+        self.connect_instrument(self.session.number_of_instruments() - 1);
+        self.save_session();
+    }
+
+    /// Removes the last instrument, a session keeps at least one.
+    pub fn remove_instrument(&mut self) {
+        let count = self.session.number_of_instruments();
+        if count <= 1 {
+            return;
+        }
+        if let Some(mut instrument) = self.instruments.pop() {
+            instrument.panic();
+        }
+        self.queued_patterns.remove(&(count - 1));
+        if matches!(self.selected, Some((instrument, _)) if instrument == count - 1) {
+            self.set_selected(None);
+        }
+        self.session.remove_instrument();
+        self.history.forget_instruments(count - 1);
+        self.save_session();
+    }
+
+    pub fn get_selected(&self) -> Option<(usize, usize)> {
+        return self.selected;
+    }
+
+    /// Sets the instrument and pattern which is edited, recordings and
+    /// keyboard notes go there.
+    pub fn set_selected(&mut self, selected: Option<(usize, usize)>) {
+        self.selected = selected;
+        self.recording = false;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        return self.recording;
+    }
+
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recording = recording && self.selected.is_some();
+        self.replaced_steps.clear();
+    }
+
+    pub fn get_record_mode(&self) -> RecordMode {
+        return self.record_mode;
+    }
+
+    pub fn set_record_mode(&mut self, mode: RecordMode) {
+        self.record_mode = mode;
+        self.replaced_steps.clear();
+    }
+
+    /// The step closest to now, either the active or the upcoming one.
+    fn get_quantized_step(&self) -> Step {
+        let half_step = self.session.get_step_length() * 500.0;
+        if self.last_step.elapsed().as_micros() as f64 > half_step {
+            return (self.active_step + 1) % BAR_SIZE;
+        }
+        return self.active_step;
+    }
+
+    /// Clears the step once per take in replace mode.
+    fn replace_step(&mut self, instrument: usize, pattern: usize, step: Step) {
+        if self.record_mode == RecordMode::Replace
+            && self.replaced_steps.insert(step)
+            && self.get_step(instrument, pattern, step).is_some()
+        {
+            self.set_step(instrument, pattern, step, None);
+        }
+    }
+
+    fn record_note(&mut self, instrument: usize, pattern: usize, note: Note, velocity: Velocity) {
+        let step = self.get_quantized_step();
+        self.replace_step(instrument, pattern, step);
+        let mut step_notes = self.get_step(instrument, pattern, step).unwrap_or_default();
+        step_notes.insert(note, velocity);
+        self.set_step(instrument, pattern, step, Some(step_notes));
+    }
+
+    fn process_thru(&mut self) {
+        if let Some(thru) = &mut self.thru {
+            let selected = self.selected.map(|(instrument, _)| {
+                (
+                    instrument,
+                    self.session.get_instrument(instrument).get_channel(),
+                )
+            });
+            thru.route(&mut self.instruments, selected);
+        }
+    }
+
+    /// Plays notes of the keyboard through the selected instrument and
+    /// records them while recording. Keys are released on the instrument
+    /// they started on, even if the selection changed in between.
+    fn process_keyboard(&mut self) {
+        loop {
+            let event = match &mut self.keyboard {
+                Some(keyboard) => keyboard.pop_event(),
+                None => None,
+            };
+            let message = match event {
+                Some(event) => event.message,
+                None => break,
+            };
+            match message.r#type {
+                MidiMessageType::NoteOn if message.velocity > 0 => {
+                    // notes played while nothing is selected are dropped
+                    let (instrument, pattern) = match self.selected {
+                        Some(selected) => selected,
+                        None => continue,
+                    };
+                    let channel = self.session.get_instrument(instrument).get_channel();
+                    self.instruments[instrument].play_note(
+                        channel,
+                        message.note,
+                        message.velocity,
+                        0.0,
+                    );
+                    let previous = self
+                        .held_keyboard_notes
+                        .insert(message.note, (instrument, channel));
+                    // a key played again without being released elsewhere
+                    if let Some((other, other_channel)) = previous {
+                        if (other, other_channel) != (instrument, channel) {
+                            if let Some(other) = self.instruments.get_mut(other) {
+                                other.stop_note(other_channel, message.note);
+                            }
+                        }
+                    }
+                    if self.recording {
+                        self.record_note(instrument, pattern, message.note, message.velocity);
+                    }
+                }
+                MidiMessageType::NoteOn | MidiMessageType::NoteOff => {
+                    if let Some((instrument, channel)) =
+                        self.held_keyboard_notes.remove(&message.note)
                     {
-                        let notes = self
-                            .session
-                            .get_instrument(instrument)
-                            .get_pattern(pattern)
-                            .unwrap()
-                            .get_step(self.active_step);
-                        for (note, velocity) in notes {
-                            println!("play {}", note);
-                            self.instruments[instrument].play_note(
-                                1,
-                                *note,
-                                *velocity,
-                                STEP_LENGTH,
-                            ); // TODO
-                            played_notes.push((instrument, *note));
+                        // removed instruments were silenced already
+                        if let Some(instrument) = self.instruments.get_mut(instrument) {
+                            instrument.stop_note(channel, message.note);
                         }
                     }
                 }
-                None => {}
+                _ => {}
+            }
+        }
+    }
+
+    fn play_notes(&mut self) -> PlayedNotes {
+        let mut played_notes = PlayedNotes::new();
+        let step_length = self.session.get_step_length();
+        for instrument in 0..self.session.number_of_instruments() {
+            if !self.session.is_audible(instrument) {
+                continue;
+            }
+            let pattern = match self.session.get_instrument(instrument).get_active_pattern() {
+                Some(pattern) => pattern,
+                None => continue,
+            };
+            let the_pattern = match &self.preview {
+                Some((i, p, preview)) if *i == instrument && *p == pattern => Some(preview),
+                _ => self.session.get_instrument(instrument).get_pattern(pattern),
+            };
+            if let Some(the_pattern) = the_pattern {
+                if the_pattern.has_step_set(self.active_step) {
+                    let channel = self.session.get_instrument(instrument).get_channel();
+                    for (note, velocity) in the_pattern.get_step(self.active_step) {
+                        let velocity = match &self.fade_out {
+                            Some(_) => match fade_velocity(*velocity, self.active_step, true) {
+                                Some(velocity) => velocity,
+                                None => continue,
+                            },
+                            None => *velocity,
+                        };
+                        println!("play {}", note);
+                        self.instruments[instrument].play_note(
+                            channel,
+                            *note,
+                            velocity,
+                            step_length,
+                        );
+                        played_notes.push((instrument, *note));
+                    }
+                }
             }
         }
+        self.play_fade_out();
         return played_notes;
     }
 
-    pub fn save_session(&self) {
-        match &self.session_file_path {
-            Some(path) => {
-                let data = self.session.to_json().unwrap();
-                println!("{} {}", path, data);
-                fs::write(path, data).expect("Unable to write file");
+    /// Plays the step of the previous session while it fades out.
+    fn play_fade_out(&mut self) {
+        let fade_out = match &mut self.fade_out {
+            Some(fade_out) => fade_out,
+            None => return,
+        };
+        let step_length = self.session.get_step_length();
+        let session = &fade_out.session;
+        for index in 0..session.number_of_instruments() {
+            if !session.is_audible(index) {
+                continue;
+            }
+            let instrument = session.get_instrument(index);
+            let the_pattern = match instrument
+                .get_active_pattern()
+                .and_then(|x| instrument.get_pattern(x))
+            {
+                Some(the_pattern) if the_pattern.has_step_set(self.active_step) => the_pattern,
+                _ => continue,
+            };
+            // instruments which kept their port play both sessions
+            let output = match fade_out.instruments.get_mut(&index) {
+                Some(output) => output,
+                None => &mut self.instruments[index],
+            };
+            for (note, velocity) in the_pattern.get_step(self.active_step) {
+                if let Some(velocity) = fade_velocity(*velocity, self.active_step, false) {
+                    output.play_note(instrument.get_channel(), *note, velocity, step_length);
+                }
+            }
+        }
+    }
+
+    /// Silences the instruments only the previous session played on, once
+    /// it faded out.
+    fn end_fade_out(&mut self) {
+        if let Some(fade_out) = self.fade_out.take() {
+            for (_, mut instrument) in fade_out.instruments {
+                instrument.panic();
             }
-            None => {}
+        }
+    }
+
+    /// Schedules the session to be written to its file in the background.
+    pub fn save_session(&self) {
+        if let Some(autosave) = &self.autosave {
+            autosave.save(&self.session);
+        }
+    }
+
+    /// Writes pending session changes and waits until they are on disk.
+    pub fn flush_session(&self) {
+        if let Some(autosave) = &self.autosave {
+            autosave.flush();
+        }
+    }
+
+    /// The notes of a step, `None` if the step or its pattern isn't set.
+    pub fn get_step(&self, instrument: usize, pattern: usize, step: Step) -> Option<StepNotes> {
+        let the_pattern = self
+            .session
+            .get_instrument(instrument)
+            .get_pattern(pattern)?;
+        if !the_pattern.has_step_set(step) {
+            return None;
+        }
+        return Some(the_pattern.get_step(step).clone());
+    }
+
+    /// Sets the notes of a step of an existing pattern, `None` clears it.
+    /// The change can be undone.
+    pub fn set_step(
+        &mut self,
+        instrument: usize,
+        pattern: usize,
+        step: Step,
+        notes: Option<StepNotes>,
+    ) {
+        if !self.session.get_instrument(instrument).has_pattern(pattern) {
+            return;
+        }
+        let edit = Edit::Step {
+            instrument: instrument,
+            pattern: pattern,
+            step: step,
+            before: self.get_step(instrument, pattern, step),
+            after: notes,
         };
+        self.history.apply(&mut self.session, edit);
+        self.save_session();
+    }
+
+    /// Replaces a pattern of an instrument. The change can be undone.
+    pub fn set_pattern(&mut self, instrument: usize, index: usize, pattern: &Pattern) {
+        let edit = Edit::Pattern {
+            instrument: instrument,
+            index: index,
+            before: self
+                .session
+                .get_instrument(instrument)
+                .get_pattern(index)
+                .cloned(),
+            after: Some(pattern.clone()),
+        };
+        self.history.apply(&mut self.session, edit);
+        self.save_session();
+    }
+
+    /// Activates a pattern right away, `None` stops the instrument. The
+    /// change can be undone.
+    pub fn set_active_pattern(&mut self, instrument: usize, pattern: Option<usize>) {
+        let edit = Edit::ActivePattern {
+            instrument: instrument,
+            before: self.session.get_instrument(instrument).get_active_pattern(),
+            after: pattern,
+        };
+        self.history.apply(&mut self.session, edit);
+        self.save_session();
+    }
+
+    /// Plays the given pattern in place of the one of the session until the
+    /// preview is reset with `None`. The session isn't changed.
+    pub fn set_preview(&mut self, preview: Option<(usize, usize, Pattern)>) {
+        self.preview = preview;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        return self.history.can_undo();
+    }
+
+    pub fn can_redo(&self) -> bool {
+        return self.history.can_redo();
+    }
+
+    pub fn undo(&mut self) {
+        if self.history.undo(&mut self.session) {
+            self.save_session();
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if self.history.redo(&mut self.session) {
+            self.save_session();
+        }
     }
 
     pub fn get_session(&self) -> &Session {
@@ -117,23 +518,327 @@ impl Sequencer {
         return self.active_step;
     }
 
+    /// Activates the pattern of the instrument at the next launch point of
+    /// the session's launch quantization, `None` stops the instrument.
+    pub fn launch_pattern(&mut self, instrument: usize, pattern: Option<usize>) {
+        let quantization = self.session.get_launch_quantization();
+        self.queue_pattern(instrument, pattern, quantization);
+    }
+
+    fn queue_pattern(
+        &mut self,
+        instrument: usize,
+        pattern: Option<usize>,
+        quantization: LaunchQuantization,
+    ) {
+        if quantization == LaunchQuantization::Immediate {
+            self.queued_patterns.remove(&instrument);
+            self.set_active_pattern(instrument, pattern);
+        } else {
+            self.queued_patterns
+                .insert(instrument, (pattern, quantization));
+        }
+    }
+
+    /// Activates the patterns of the scene for all instruments at the next
+    /// bar.
+    pub fn launch_scene(&mut self, scene: &Scene) {
+        for instrument in 0..self.session.number_of_instruments() {
+            self.queue_pattern(
+                instrument,
+                scene.get_pattern(instrument),
+                LaunchQuantization::Bar,
+            );
+        }
+    }
+
+    /// The pattern waiting to be launched for the instrument, if any.
+    pub fn get_queued_pattern(&self, instrument: usize) -> Option<Option<usize>> {
+        return self.queued_patterns.get(&instrument).map(|x| x.0);
+    }
+
+    fn launch_queued_patterns(&mut self) {
+        let active_step = self.active_step;
+        let due: Vec<(usize, Option<usize>)> = self
+            .queued_patterns
+            .iter()
+            .filter(|(_, (_, quantization))| active_step.is_multiple_of(quantization.get_steps()))
+            .map(|(instrument, (pattern, _))| (*instrument, *pattern))
+            .collect();
+        for (instrument, pattern) in due {
+            self.queued_patterns.remove(&instrument);
+            self.set_active_pattern(instrument, pattern);
+        }
+    }
+
+    /// Follows the arrangement from the given scene on, starting at the
+    /// next bar.
+    pub fn play_song(&mut self, scene: usize) {
+        if scene < self.session.get_arrangement().len() {
+            self.song_position = Some(SongPosition {
+                scene: scene,
+                bars_played: 0,
+                pending: true,
+            });
+        }
+    }
+
+    pub fn stop_song(&mut self) {
+        self.song_position = None;
+    }
+
+    /// The scene of the arrangement which is playing or about to play.
+    pub fn get_song_scene(&self) -> Option<usize> {
+        return self.song_position.as_ref().map(|x| x.scene);
+    }
+
+    fn apply_scene(&mut self, scene: usize) {
+        let patterns: Vec<Option<usize>> = match self.session.get_arrangement().get_scene(scene) {
+            Some(the_scene) => (0..self.session.number_of_instruments())
+                .map(|x| the_scene.get_pattern(x))
+                .collect(),
+            None => return,
+        };
+        self.queued_patterns.clear();
+        for (instrument, pattern) in patterns.into_iter().enumerate() {
+            self.session
+                .get_instrument_mut(instrument)
+                .set_active_pattern(pattern);
+        }
+        self.save_session();
+    }
+
+    /// Moves along the arrangement, called at the start of every bar.
+    fn advance_song(&mut self) {
+        let position = match &mut self.song_position {
+            Some(position) => position,
+            None => return,
+        };
+        if position.pending {
+            position.pending = false;
+            let scene = position.scene;
+            self.apply_scene(scene);
+            return;
+        }
+        position.bars_played += 1;
+        let arrangement = self.session.get_arrangement();
+        let bars = match arrangement.get_scene(position.scene) {
+            Some(scene) => scene.get_bars(),
+            None => 0,
+        };
+        if position.bars_played < bars {
+            return;
+        }
+        match arrangement.get_next_scene(position.scene) {
+            Some(next) => {
+                position.scene = next;
+                position.bars_played = 0;
+                self.apply_scene(next);
+            }
+            None => {
+                // end of the song
+                self.song_position = None;
+                for instrument in 0..self.session.number_of_instruments() {
+                    self.session
+                        .get_instrument_mut(instrument)
+                        .set_active_pattern(None);
+                }
+                self.save_session();
+            }
+        }
+    }
+
+    pub fn set_pattern_library(&mut self, library: PatternLibrary) {
+        self.pattern_library = library;
+    }
+
+    pub fn get_pattern_library(&self) -> &PatternLibrary {
+        return &self.pattern_library;
+    }
+
+    pub fn get_projects(&self) -> Option<&ProjectLibrary> {
+        return self.projects.as_ref();
+    }
+
+    /// The project currently played.
+    pub fn get_project(&self) -> usize {
+        return self.project;
+    }
+
+    /// The project which is switched to at the next bar.
+    pub fn get_queued_project(&self) -> Option<usize> {
+        return self.queued_project.as_ref().map(|x| x.0);
+    }
+
+    /// Loads the session of the project in the background and switches to it
+    /// at the first bar after it is loaded.
+    pub fn queue_project(&mut self, project: usize) {
+        let projects = match &self.projects {
+            Some(projects) => projects.clone(),
+            None => return,
+        };
+        if project == self.project {
+            self.queued_project = None;
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(projects.load(project, DEFAULT_NUMBER_OF_INSTRUMENTS));
+        });
+        self.save_session();
+        self.queued_project = Some((project, receiver));
+    }
+
+    /// The session of the queued project once it is loaded, the project stays
+    /// queued while it is still loading.
+    fn take_loaded_project(&mut self) -> Option<(usize, Session)> {
+        let (project, result) = match &self.queued_project {
+            Some((project, loader)) => match loader.try_recv() {
+                Ok(result) => (*project, result.map_err(|e| e.to_string())),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {
+                    (*project, Err("the loader stopped".to_string()))
+                }
+            },
+            None => return None,
+        };
+        self.queued_project = None;
+        if let Err(e) = &result {
+            println!(
+                "Unable to load {}: {}",
+                self.projects
+                    .as_ref()
+                    .and_then(|x| x.get_path(project))
+                    .unwrap_or_default(),
+                e
+            );
+        }
+        return result.ok().map(|session| (project, session));
+    }
+
+    /// Replaces the session by the queued one if it is loaded. Over the first
+    /// bar the new session fades in while the previous one keeps playing and
+    /// fades out, on the instruments it played on before.
+    fn switch_project(&mut self) {
+        let (project, session) = match self.take_loaded_project() {
+            Some(loaded) => loaded,
+            None => return,
+        };
+        let projects = self.projects.as_ref().unwrap();
+        println!("switch to project {}", projects.get_name(project).unwrap());
+        self.save_session();
+        if let Some(autosave) = &self.autosave {
+            autosave.set_path(projects.get_path(project).unwrap());
+        }
+        let previous = std::mem::replace(&mut self.session, session);
+        self.project = project;
+        let count = self.session.number_of_instruments();
+        let mut fading_instruments = HashMap::new();
+        while self.instruments.len() > count {
+            if let Some(instrument) = self.instruments.pop() {
+                fading_instruments.insert(self.instruments.len(), instrument);
+            }
+        }
+        for index in 0..self.instruments.len() {
+            if previous.get_instrument(index).get_port()
+                != self.session.get_instrument(index).get_port()
+            {
+                let instrument = self.create_instrument(index);
+                let previous_instrument =
+                    std::mem::replace(&mut self.instruments[index], instrument);
+                fading_instruments.insert(index, previous_instrument);
+            }
+        }
+        for index in self.instruments.len()..count {
+            self.connect_instrument(index);
+        }
+        self.fade_out = Some(FadeOut {
+            session: previous,
+            instruments: fading_instruments,
+        });
+        // the edits and launches refer to the previous session
+        self.history = History::new(DEFAULT_HISTORY_SIZE);
+        self.queued_patterns.clear();
+        self.song_position = None;
+        self.recording = false;
+        self.preview = None;
+        if matches!(self.selected, Some((instrument, _)) if instrument >= count) {
+            self.selected = None;
+        }
+        self.save_session();
+    }
+
     pub fn process_step(&mut self) -> PlayedNotes {
         self.active_step = (self.active_step + 1) % BAR_SIZE;
+        if self.active_step == 0 {
+            self.end_fade_out();
+            self.switch_project();
+        }
+        self.launch_queued_patterns();
+        if self.active_step == 0 {
+            self.advance_song();
+        }
+        if self.recording {
+            let (instrument, pattern) = self.selected.unwrap();
+            self.replace_step(instrument, pattern, self.active_step);
+        }
         return self.play_notes();
     }
 
     pub fn wait(&mut self) -> WaitResult {
-        if self.last_step.elapsed().as_micros() >= (STEP_LENGTH * 1000.0).floor() as u128 {
+        let step_length = self.session.get_step_length();
+        if self.last_step.elapsed().as_micros() >= (step_length * 1000.0).floor() as u128 {
             self.last_step = Instant::now();
             return WaitResult::Step;
         }
-        for n in 0..NUMBER_OF_INSTRUMENTS {
-            self.instruments[n].send_events();
+        self.process_keyboard();
+        self.process_thru();
+        for instrument in &mut self.instruments {
+            instrument.send_events();
         }
         return WaitResult::Intermediate;
     }
 
+    /// The scheduler shared by the outputs of all instruments.
+    pub fn get_scheduler(&self) -> SharedScheduler {
+        return self.scheduler.clone();
+    }
+
     pub fn get_instrument(&mut self, index: usize) -> &mut Instrument {
         return &mut self.instruments[index];
     }
+
+    /// Silences all instruments.
+    pub fn panic(&mut self) {
+        self.end_fade_out();
+        for instrument in &mut self.instruments {
+            instrument.panic();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_cross_fade_over_the_bar() {
+        let last = BAR_SIZE - 1;
+        assert_eq!(fade_velocity(127, 0, false), Some(127));
+        assert_eq!(fade_velocity(127, last, true), Some(127));
+        assert_eq!(fade_velocity(127, BAR_SIZE / 2 - 1, true), Some(63));
+        assert_eq!(fade_velocity(127, BAR_SIZE / 2, false), Some(63));
+        // the fading sessions never get louder
+        for step in 1..BAR_SIZE {
+            assert!(fade_velocity(100, step, true) >= fade_velocity(100, step - 1, true));
+            assert!(fade_velocity(100, step, false) <= fade_velocity(100, step - 1, false));
+        }
+    }
+
+    #[test]
+    fn quiet_notes_fade_to_silence() {
+        assert_eq!(fade_velocity(10, 0, true), None);
+        assert_eq!(fade_velocity(10, BAR_SIZE - 1, false), None);
+        assert_eq!(fade_velocity(0, BAR_SIZE - 1, true), None);
+    }
 }
@@ -1,5 +1,11 @@
+mod migrations;
+
+use super::generators::RandomSettings;
+use super::random::Random;
+use super::scale::Scale;
+use migrations::FORMAT_VERSION;
 use serde::{Deserialize, Serialize};
-use serde_json::Result;
+use serde_json::{Result, Value};
 use std::collections::HashMap;
 
 pub type Step = u8;
@@ -7,18 +13,54 @@ pub type Channel = u8;
 pub type Note = u8;
 pub type Velocity = u8;
 pub const BAR_SIZE: Step = 32;
+pub const STEPS_PER_BEAT: Step = 4;
+pub const DEFAULT_BPM: f64 = 126.0;
+pub const DEFAULT_CHANNEL: Channel = 1;
 pub type StepNotes = HashMap<Note, Velocity>;
 
 pub type Bar = HashMap<Step, StepNotes>;
 
+/// When a newly activated pattern starts playing.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum LaunchQuantization {
+    Immediate,
+    Beat,
+    Bar,
+}
+
+impl LaunchQuantization {
+    /// Number of steps between two launch points.
+    pub fn get_steps(&self) -> Step {
+        match self {
+            LaunchQuantization::Immediate => 1,
+            LaunchQuantization::Beat => STEPS_PER_BEAT,
+            LaunchQuantization::Bar => BAR_SIZE,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Pattern {
     bar: Bar,
+    /// Settings of the random generator if the pattern was generated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    random: Option<RandomSettings>,
 }
 
 impl Pattern {
     pub fn new() -> Pattern {
-        Pattern { bar: Bar::new() }
+        Pattern {
+            bar: Bar::new(),
+            random: None,
+        }
+    }
+
+    pub fn get_random_settings(&self) -> Option<&RandomSettings> {
+        return self.random.as_ref();
+    }
+
+    pub fn set_random_settings(&mut self, settings: Option<RandomSettings>) {
+        self.random = settings;
     }
 
     pub fn set_step(&mut self, step: Step, notes: &StepNotes) {
@@ -37,12 +79,264 @@ impl Pattern {
     pub fn has_step_set(&self, step: Step) -> bool {
         return self.bar.contains_key(&step);
     }
+
+    pub fn get_steps(&self) -> &Bar {
+        return &self.bar;
+    }
+
+    /// Moves all steps by the given number, to the right if positive, steps
+    /// leaving the bar come back on the other side.
+    pub fn rotate(&mut self, steps: i32) {
+        let size = BAR_SIZE as i32;
+        self.bar = self
+            .bar
+            .drain()
+            .map(|(step, notes)| (((step as i32 + steps).rem_euclid(size)) as Step, notes))
+            .collect();
+    }
+
+    /// Plays the steps backwards.
+    pub fn reverse(&mut self) {
+        self.bar = self
+            .bar
+            .drain()
+            .filter(|(step, _)| *step < BAR_SIZE)
+            .map(|(step, notes)| (BAR_SIZE - 1 - step, notes))
+            .collect();
+    }
+
+    /// Plays the first half of the bar over the whole bar, the second half is
+    /// dropped.
+    pub fn half_speed(&mut self) {
+        self.bar = self
+            .bar
+            .drain()
+            .filter(|(step, _)| *step < BAR_SIZE / 2)
+            .map(|(step, notes)| (step * 2, notes))
+            .collect();
+    }
+
+    /// Squeezes the bar into its first half and repeats it in the second one.
+    /// Notes of odd steps join those of the step before.
+    pub fn double_speed(&mut self) {
+        let mut bar = Bar::new();
+        for (step, notes) in self.bar.drain().filter(|(step, _)| *step < BAR_SIZE) {
+            for start in [0, BAR_SIZE / 2] {
+                bar.entry(start + step / 2)
+                    .or_default()
+                    .extend(notes.iter());
+            }
+        }
+        self.bar = bar;
+    }
+
+    /// Shifts all notes by the given number of semitones, notes leaving the
+    /// MIDI range are dropped.
+    pub fn transpose(&mut self, semitones: i32) {
+        for notes in self.bar.values_mut() {
+            *notes = notes
+                .drain()
+                .map(|(note, velocity)| (note as i32 + semitones, velocity))
+                .filter(|(note, _)| (0..=127).contains(note))
+                .map(|(note, velocity)| (note as Note, velocity))
+                .collect();
+        }
+        self.bar.retain(|_, notes| !notes.is_empty());
+    }
+
+    /// Varies every velocity randomly by up to the given amount.
+    pub fn humanize(&mut self, amount: u8, random: &mut Random) {
+        for notes in self.bar.values_mut() {
+            for velocity in notes.values_mut() {
+                let offset = random.range(-(amount as i32), amount as i32);
+                *velocity = (*velocity as i32 + offset).clamp(1, 127) as Velocity;
+            }
+        }
+    }
+
+    /// Checks the steps and notes are within range, returns a description of
+    /// every problem found.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut steps: Vec<_> = self.bar.iter().collect();
+        steps.sort_by_key(|x| *x.0);
+        for (step, notes) in steps {
+            if *step >= BAR_SIZE {
+                problems.push(format!(
+                    "step {}: beyond the pattern length of {}",
+                    step, BAR_SIZE
+                ));
+            }
+            let mut notes: Vec<_> = notes.iter().collect();
+            notes.sort();
+            for (note, velocity) in notes {
+                if *note > 127 {
+                    problems.push(format!("step {}: note {} is not within 0-127", step, note));
+                }
+                if *velocity > 127 {
+                    problems.push(format!(
+                        "step {}: velocity {} of note {} is not within 0-127",
+                        step, velocity, note
+                    ));
+                }
+            }
+        }
+        if let Some(settings) = &self.random {
+            for problem in settings.validate() {
+                problems.push(format!("random: {}", problem));
+            }
+        }
+        return problems;
+    }
+}
+
+/// A sound of a drum instrument, the name tells the pads apart.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Drum {
+    name: String,
+    note: Note,
+}
+
+impl Drum {
+    pub fn new(name: &str, note: Note) -> Drum {
+        Drum {
+            name: name.to_string(),
+            note: note,
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        return &self.name;
+    }
+
+    /// A drum named after the General MIDI drum of the note, or the note
+    /// itself outside of the drum map.
+    pub fn with_note(note: Note) -> Drum {
+        let name = note
+            .checked_sub(GM_FIRST_DRUM)
+            .and_then(|x| GM_DRUM_NAMES.get(x as usize));
+        match name {
+            Some(name) => Drum::new(name, note),
+            None => Drum::new(&format!("note {}", note), note),
+        }
+    }
+
+    pub fn get_note(&self) -> Note {
+        return self.note;
+    }
+}
+
+/// The lowest note of the General MIDI drum map.
+const GM_FIRST_DRUM: Note = 35;
+/// Names of the General MIDI drums, from `GM_FIRST_DRUM` on.
+const GM_DRUM_NAMES: [&str; 47] = [
+    "acoustic kick",
+    "kick",
+    "rimshot",
+    "snare",
+    "clap",
+    "electric snare",
+    "low floor tom",
+    "closed hat",
+    "high floor tom",
+    "pedal hat",
+    "low tom",
+    "open hat",
+    "low mid tom",
+    "high mid tom",
+    "crash",
+    "high tom",
+    "ride",
+    "china",
+    "ride bell",
+    "tambourine",
+    "splash",
+    "cowbell",
+    "crash 2",
+    "vibraslap",
+    "ride 2",
+    "high bongo",
+    "low bongo",
+    "mute high conga",
+    "open high conga",
+    "low conga",
+    "high timbale",
+    "low timbale",
+    "high agogo",
+    "low agogo",
+    "cabasa",
+    "maracas",
+    "short whistle",
+    "long whistle",
+    "short guiro",
+    "long guiro",
+    "claves",
+    "high wood block",
+    "low wood block",
+    "mute cuica",
+    "open cuica",
+    "mute triangle",
+    "open triangle",
+];
+
+/// The General MIDI drum kit, the most used sounds first.
+fn default_drums() -> Vec<Drum> {
+    return [
+        36, 38, 42, 46, 39, 37, 44, 49, 45, 47, 50, 51, 53, 54, 56, 70,
+    ]
+    .iter()
+    .map(|x| Drum::with_note(*x))
+    .collect();
+}
+
+/// How the pattern screen lays out the drum pads.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum DrumGrid {
+    /// Two rows of eight pads.
+    EightByTwo,
+    /// Four rows of four pads.
+    FourByFour,
 }
 
-#[derive(Serialize, Deserialize)]
+fn default_drum_grid() -> DrumGrid {
+    DrumGrid::EightByTwo
+}
+
+fn default_channel() -> Channel {
+    DEFAULT_CHANNEL
+}
+
+fn default_bpm() -> f64 {
+    DEFAULT_BPM
+}
+
+fn default_launch_quantization() -> LaunchQuantization {
+    LaunchQuantization::Bar
+}
+
+fn default_scale() -> Scale {
+    Scale::Major
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Instrument {
     patterns: HashMap<usize, Pattern>,
     active_pattern: Option<usize>,
+    #[serde(default = "default_channel")]
+    channel: Channel,
+    #[serde(default)]
+    port: u8,
+    #[serde(default)]
+    muted: bool,
+    #[serde(default)]
+    soloed: bool,
+    /// Shows the drums on the pattern screen instead of a keyboard.
+    #[serde(default)]
+    drum: bool,
+    #[serde(default = "default_drums")]
+    drums: Vec<Drum>,
+    #[serde(default = "default_drum_grid")]
+    drum_grid: DrumGrid,
 }
 
 impl Instrument {
@@ -50,6 +344,13 @@ impl Instrument {
         Instrument {
             patterns: HashMap::new(),
             active_pattern: None,
+            channel: DEFAULT_CHANNEL,
+            port: 0,
+            muted: false,
+            soloed: false,
+            drum: false,
+            drums: default_drums(),
+            drum_grid: default_drum_grid(),
         }
     }
 
@@ -69,6 +370,10 @@ impl Instrument {
         self.patterns.insert(index, pattern.clone());
     }
 
+    pub fn remove_pattern(&mut self, index: usize) {
+        self.patterns.remove(&index);
+    }
+
     pub fn get_active_pattern(&self) -> Option<usize> {
         return self.active_pattern;
     }
@@ -76,11 +381,186 @@ impl Instrument {
     pub fn set_active_pattern(&mut self, pattern: Option<usize>) {
         self.active_pattern = pattern;
     }
+
+    pub fn get_channel(&self) -> Channel {
+        return self.channel;
+    }
+
+    /// Index of the MIDI output port the instrument is connected to.
+    pub fn get_port(&self) -> u8 {
+        return self.port;
+    }
+
+    pub fn is_muted(&self) -> bool {
+        return self.muted;
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub fn is_soloed(&self) -> bool {
+        return self.soloed;
+    }
+
+    pub fn set_soloed(&mut self, soloed: bool) {
+        self.soloed = soloed;
+    }
+
+    pub fn is_drum(&self) -> bool {
+        return self.drum;
+    }
+
+    pub fn set_drum(&mut self, drum: bool) {
+        self.drum = drum;
+    }
+
+    pub fn get_drums(&self) -> &[Drum] {
+        return &self.drums;
+    }
+
+    /// Plays another note with the drum at the index, named after it.
+    pub fn set_drum_note(&mut self, index: usize, note: Note) {
+        if let Some(drum) = self.drums.get_mut(index) {
+            *drum = Drum::with_note(note);
+        }
+    }
+
+    pub fn get_drum_grid(&self) -> DrumGrid {
+        return self.drum_grid;
+    }
+
+    pub fn set_drum_grid(&mut self, grid: DrumGrid) {
+        self.drum_grid = grid;
+    }
+}
+
+/// A section of a song, saying which pattern every instrument plays and for
+/// how many bars.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Scene {
+    patterns: Vec<Option<usize>>,
+    bars: u8,
+}
+
+impl Scene {
+    pub fn new(patterns: Vec<Option<usize>>, bars: u8) -> Scene {
+        Scene { patterns, bars }
+    }
+
+    /// The pattern played by the instrument, `None` if it is silent.
+    pub fn get_pattern(&self, instrument: usize) -> Option<usize> {
+        return self.patterns.get(instrument).copied().flatten();
+    }
+
+    pub fn get_bars(&self) -> u8 {
+        return self.bars;
+    }
+
+    /// Forgets the patterns of instruments from the given index on.
+    pub fn truncate(&mut self, number_of_instruments: usize) {
+        self.patterns.truncate(number_of_instruments);
+    }
+
+    pub fn set_bars(&mut self, bars: u8) {
+        self.bars = bars.max(1);
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+/// An ordered chain of scenes, optionally repeating a range of them.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Arrangement {
+    scenes: Vec<Scene>,
+    loop_start: Option<usize>,
+    loop_end: Option<usize>,
+}
+
+impl Arrangement {
+    pub fn len(&self) -> usize {
+        return self.scenes.len();
+    }
+
+    pub fn get_scene(&self, index: usize) -> Option<&Scene> {
+        return self.scenes.get(index);
+    }
+
+    pub fn get_scene_mut(&mut self, index: usize) -> Option<&mut Scene> {
+        return self.scenes.get_mut(index);
+    }
+
+    pub fn insert_scene(&mut self, index: usize, scene: Scene) {
+        self.scenes.insert(index.min(self.scenes.len()), scene);
+        self.clamp_loop();
+    }
+
+    pub fn remove_scene(&mut self, index: usize) {
+        if index < self.scenes.len() {
+            self.scenes.remove(index);
+            self.clamp_loop();
+        }
+    }
+
+    pub fn get_loop_start(&self) -> Option<usize> {
+        return self.loop_start;
+    }
+
+    pub fn set_loop_start(&mut self, index: Option<usize>) {
+        self.loop_start = index;
+        self.clamp_loop();
+    }
+
+    pub fn get_loop_end(&self) -> Option<usize> {
+        return self.loop_end;
+    }
+
+    pub fn set_loop_end(&mut self, index: Option<usize>) {
+        self.loop_end = index;
+        self.clamp_loop();
+    }
+
+    fn clamp_loop(&mut self) {
+        let last = self.scenes.len().checked_sub(1);
+        self.loop_start = self.loop_start.and_then(|x| last.map(|last| x.min(last)));
+        self.loop_end = self.loop_end.and_then(|x| last.map(|last| x.min(last)));
+    }
+
+    /// Whether the scene lies within the loop points.
+    pub fn is_looped(&self, index: usize) -> bool {
+        match (self.loop_start, self.loop_end) {
+            (Some(start), Some(end)) => start <= index && index <= end,
+            _ => false,
+        }
+    }
+
+    /// The scene following the given one, `None` at the end of the song.
+    pub fn get_next_scene(&self, index: usize) -> Option<usize> {
+        if let (Some(start), Some(end)) = (self.loop_start, self.loop_end) {
+            if index == end && start <= end {
+                return Some(start);
+            }
+        }
+        if index + 1 < self.scenes.len() {
+            return Some(index + 1);
+        }
+        return None;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Session {
+    format_version: u64,
     instruments: Vec<Instrument>,
+    #[serde(default = "default_bpm")]
+    bpm: f64,
+    #[serde(default = "default_launch_quantization")]
+    launch_quantization: LaunchQuantization,
+    #[serde(default)]
+    arrangement: Arrangement,
+    /// Pitch class of the key, 0 for C.
+    #[serde(default)]
+    root: Note,
+    #[serde(default = "default_scale")]
+    scale: Scale,
 }
 
 impl Session {
@@ -90,15 +570,180 @@ impl Session {
             instruments.push(Instrument::new());
         }
         return Session {
+            format_version: FORMAT_VERSION,
             instruments: instruments,
+            bpm: DEFAULT_BPM,
+            launch_quantization: default_launch_quantization(),
+            arrangement: Arrangement::default(),
+            root: 0,
+            scale: default_scale(),
         };
     }
 
+    /// Reads a session, upgrading files written by older versions.
     pub fn from_json(json: &str) -> Result<Session> {
-        let s: Session = serde_json::from_str(json)?;
+        let mut value: Value = serde_json::from_str(json)?;
+        migrations::migrate(&mut value)?;
+        // the content is checked by `validate`, which lists every problem
+        let s: Session = serde_json::from_value(value)?;
         Ok(s)
     }
 
+    /// Checks that the session can be played, returns a description of every
+    /// problem found.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.instruments.is_empty() {
+            problems.push("the session has no instruments".to_string());
+        }
+        if self.bpm.is_nan() || self.bpm <= 0.0 {
+            problems.push(format!("the tempo {} is not positive", self.bpm));
+        }
+        if self.root > 11 {
+            problems.push(format!("the root {} is not within 0-11", self.root));
+        }
+        for problem in self.scale.validate() {
+            problems.push(format!("scale: {}", problem));
+        }
+        for (index, instrument) in self.instruments.iter().enumerate() {
+            let name = format!("instrument {}", index);
+            if !(1..=16).contains(&instrument.channel) {
+                problems.push(format!(
+                    "{}: channel {} is not within 1-16",
+                    name, instrument.channel
+                ));
+            }
+            if let Some(active) = instrument.active_pattern {
+                if !instrument.has_pattern(active) {
+                    problems.push(format!(
+                        "{}: the active pattern {} doesn't exist",
+                        name, active
+                    ));
+                }
+            }
+            for drum in &instrument.drums {
+                if drum.note > 127 {
+                    problems.push(format!(
+                        "{}: note {} of drum {} is not within 0-127",
+                        name, drum.note, drum.name
+                    ));
+                }
+            }
+            let mut pattern_indices: Vec<&usize> = instrument.patterns.keys().collect();
+            pattern_indices.sort();
+            for pattern_index in pattern_indices {
+                for problem in instrument.patterns[pattern_index].validate() {
+                    problems.push(format!("{}, pattern {}, {}", name, pattern_index, problem));
+                }
+            }
+        }
+        for (index, scene) in self.arrangement.scenes.iter().enumerate() {
+            if scene.patterns.len() > self.instruments.len() {
+                problems.push(format!(
+                    "scene {}: patterns for {} instruments but the session has {}",
+                    index,
+                    scene.patterns.len(),
+                    self.instruments.len()
+                ));
+            }
+            if scene.bars == 0 {
+                problems.push(format!("scene {}: the length is zero bars", index));
+            }
+        }
+        let scenes = self.arrangement.len();
+        for (name, point) in [
+            ("loop start", self.arrangement.loop_start),
+            ("loop end", self.arrangement.loop_end),
+        ] {
+            if let Some(point) = point.filter(|x| *x >= scenes) {
+                problems.push(format!(
+                    "the {} {} is beyond the {} scenes of the arrangement",
+                    name, point, scenes
+                ));
+            }
+        }
+        return problems;
+    }
+
+    pub fn add_instrument(&mut self) {
+        self.instruments.push(Instrument::new());
+    }
+
+    /// Removes the last instrument along with its place in the arrangement.
+    pub fn remove_instrument(&mut self) {
+        self.instruments.pop();
+        let count = self.instruments.len();
+        for index in 0..self.arrangement.len() {
+            if let Some(scene) = self.arrangement.get_scene_mut(index) {
+                scene.truncate(count);
+            }
+        }
+    }
+
+    pub fn number_of_instruments(&self) -> usize {
+        return self.instruments.len();
+    }
+
+    pub fn get_bpm(&self) -> f64 {
+        return self.bpm;
+    }
+
+    pub fn get_launch_quantization(&self) -> LaunchQuantization {
+        return self.launch_quantization;
+    }
+
+    pub fn set_launch_quantization(&mut self, quantization: LaunchQuantization) {
+        self.launch_quantization = quantization;
+    }
+
+    /// Pitch class of the key, 0 for C.
+    pub fn get_root(&self) -> Note {
+        return self.root;
+    }
+
+    pub fn set_root(&mut self, root: Note) {
+        self.root = root % 12;
+    }
+
+    pub fn get_scale(&self) -> &Scale {
+        return &self.scale;
+    }
+
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+    }
+
+    pub fn get_arrangement(&self) -> &Arrangement {
+        return &self.arrangement;
+    }
+
+    pub fn get_arrangement_mut(&mut self) -> &mut Arrangement {
+        return &mut self.arrangement;
+    }
+
+    /// A scene of the patterns which are currently active.
+    pub fn capture_scene(&self, bars: u8) -> Scene {
+        return Scene::new(
+            self.instruments
+                .iter()
+                .map(|x| x.get_active_pattern())
+                .collect(),
+            bars,
+        );
+    }
+
+    /// Length of a single step in milliseconds at the session tempo.
+    pub fn get_step_length(&self) -> f64 {
+        return 1000.0 * 60.0 / (STEPS_PER_BEAT as f64 * self.bpm);
+    }
+
+    /// Whether the instrument is heard, taking mutes and solos into account.
+    pub fn is_audible(&self, index: usize) -> bool {
+        let instrument = &self.instruments[index];
+        let any_soloed = self.instruments.iter().any(|x| x.is_soloed());
+        return !instrument.is_muted() && (!any_soloed || instrument.is_soloed());
+    }
+
     pub fn get_instrument(&self, index: usize) -> &Instrument {
         &self.instruments[index]
     }
@@ -112,3 +757,166 @@ impl Session {
         Ok(j)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(notes: &[(Step, Note, Velocity)]) -> Pattern {
+        let mut pattern = Pattern::new();
+        for (step, note, velocity) in notes {
+            let mut step_notes = if pattern.has_step_set(*step) {
+                pattern.get_step(*step).clone()
+            } else {
+                StepNotes::new()
+            };
+            step_notes.insert(*note, *velocity);
+            pattern.set_step(*step, &step_notes);
+        }
+        return pattern;
+    }
+
+    /// The notes of the pattern ordered by step and note.
+    fn notes(pattern: &Pattern) -> Vec<(Step, Note, Velocity)> {
+        let mut notes: Vec<(Step, Note, Velocity)> = pattern
+            .get_steps()
+            .iter()
+            .flat_map(|(step, notes)| notes.iter().map(move |(n, v)| (*step, *n, *v)))
+            .collect();
+        notes.sort();
+        return notes;
+    }
+
+    #[test]
+    fn rotate_left() {
+        let mut the_pattern = pattern(&[(0, 36, 100), (5, 38, 90)]);
+        the_pattern.rotate(-1);
+        assert_eq!(notes(&the_pattern), vec![(4, 38, 90), (31, 36, 100)]);
+    }
+
+    #[test]
+    fn rotate_right() {
+        let mut the_pattern = pattern(&[(0, 36, 100), (31, 38, 90)]);
+        the_pattern.rotate(1);
+        assert_eq!(notes(&the_pattern), vec![(0, 38, 90), (1, 36, 100)]);
+    }
+
+    #[test]
+    fn reverse() {
+        let mut the_pattern = pattern(&[(0, 36, 100), (10, 38, 90)]);
+        the_pattern.reverse();
+        assert_eq!(notes(&the_pattern), vec![(21, 38, 90), (31, 36, 100)]);
+    }
+
+    #[test]
+    fn half_speed() {
+        let mut the_pattern = pattern(&[(1, 36, 100), (15, 38, 90), (16, 42, 80)]);
+        the_pattern.half_speed();
+        assert_eq!(notes(&the_pattern), vec![(2, 36, 100), (30, 38, 90)]);
+    }
+
+    #[test]
+    fn double_speed() {
+        let mut the_pattern = pattern(&[(0, 36, 100), (1, 42, 80), (30, 38, 90)]);
+        the_pattern.double_speed();
+        assert_eq!(
+            notes(&the_pattern),
+            vec![
+                (0, 36, 100),
+                (0, 42, 80),
+                (15, 38, 90),
+                (16, 36, 100),
+                (16, 42, 80),
+                (31, 38, 90),
+            ]
+        );
+    }
+
+    #[test]
+    fn transpose_up() {
+        let mut the_pattern = pattern(&[(0, 60, 100), (4, 64, 90)]);
+        the_pattern.transpose(12);
+        assert_eq!(notes(&the_pattern), vec![(0, 72, 100), (4, 76, 90)]);
+    }
+
+    #[test]
+    fn transpose_down() {
+        let mut the_pattern = pattern(&[(0, 60, 100), (4, 64, 90)]);
+        the_pattern.transpose(-1);
+        assert_eq!(notes(&the_pattern), vec![(0, 59, 100), (4, 63, 90)]);
+    }
+
+    #[test]
+    fn transpose_out_of_range_drops_notes() {
+        let mut the_pattern = pattern(&[(0, 120, 100), (0, 60, 100), (4, 125, 90)]);
+        the_pattern.transpose(12);
+        assert_eq!(notes(&the_pattern), vec![(0, 72, 100)]);
+        assert!(!the_pattern.has_step_set(4));
+        let mut the_pattern = pattern(&[(0, 5, 100)]);
+        the_pattern.transpose(-12);
+        assert!(the_pattern.get_steps().is_empty());
+    }
+
+    #[test]
+    fn humanize_stays_within_bounds() {
+        let mut the_pattern = pattern(&[(0, 36, 1), (1, 38, 64), (2, 42, 127)]);
+        let mut random = Random::new(7);
+        for _ in 0..100 {
+            let mut humanized = the_pattern.clone();
+            humanized.humanize(16, &mut random);
+            for ((step, note, before), (_, _, after)) in
+                notes(&the_pattern).into_iter().zip(notes(&humanized))
+            {
+                assert!((1..=127).contains(&after), "step {} note {}", step, note);
+                assert!((after as i32 - before as i32).abs() <= 16);
+            }
+        }
+        the_pattern.humanize(0, &mut random);
+        assert_eq!(
+            notes(&the_pattern),
+            vec![(0, 36, 1), (1, 38, 64), (2, 42, 127)]
+        );
+    }
+
+    #[test]
+    fn humanize_is_reproducible_with_a_seed() {
+        let the_pattern = pattern(&[(0, 36, 100), (1, 38, 64)]);
+        let mut first = the_pattern.clone();
+        first.humanize(16, &mut Random::new(42));
+        let mut second = the_pattern.clone();
+        second.humanize(16, &mut Random::new(42));
+        assert_eq!(notes(&first), notes(&second));
+    }
+
+    #[test]
+    fn stored_random_settings_are_validated() {
+        let json = r#"{
+            "format_version": 1,
+            "instruments": [{
+                "patterns": {"0": {"bar": {}, "random": {
+                    "seed": 1, "density": 150, "notes": [36, 200],
+                    "min_velocity": 100, "max_velocity": 50
+                }}},
+                "active_pattern": 0
+            }]
+        }"#;
+        let problems = Session::from_json(json).unwrap().validate();
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems
+            .iter()
+            .all(|x| x.starts_with("instrument 0, pattern 0, random: ")));
+    }
+
+    #[test]
+    fn sessions_without_instruments_are_reported_by_validate() {
+        let json = r#"{"format_version": 2, "instruments": [], "bpm": 0.0}"#;
+        let problems = Session::from_json(json).unwrap().validate();
+        assert_eq!(
+            problems,
+            vec![
+                "the session has no instruments".to_string(),
+                "the tempo 0 is not positive".to_string()
+            ]
+        );
+    }
+}
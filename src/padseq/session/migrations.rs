@@ -0,0 +1,206 @@
+use super::{default_drum_grid, default_drums, default_scale, DEFAULT_BPM, DEFAULT_CHANNEL};
+use serde_json::{json, Map, Result, Value};
+
+/// Version written by this build. Files without a version are version 0.
+pub const FORMAT_VERSION: u64 = 2;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// Upgrades from version `i` to `i + 1`, one entry per version.
+const MIGRATIONS: [Migration; FORMAT_VERSION as usize] = [migrate_v0, migrate_v1];
+
+fn error(message: &str) -> serde_json::Error {
+    return serde::de::Error::custom(message);
+}
+
+/// Brings a session file of any older version to the current layout.
+pub fn migrate(value: &mut Value) -> Result<()> {
+    let session = value
+        .as_object_mut()
+        .ok_or_else(|| error("a session must be a JSON object"))?;
+    let version = match session.get("format_version") {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| error("format_version must be a number"))?,
+        None => 0,
+    };
+    if version > FORMAT_VERSION {
+        return Err(error(&format!(
+            "format version {} is newer than the supported version {}",
+            version, FORMAT_VERSION
+        )));
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(session)?;
+    }
+    session.insert("format_version".to_string(), json!(FORMAT_VERSION));
+    return Ok(());
+}
+
+fn insert_default(object: &mut Map<String, Value>, key: &str, value: Value) {
+    object.entry(key.to_string()).or_insert(value);
+}
+
+/// Unversioned files grew fields over time, older ones lack the tempo, the
+/// launch quantization, the arrangement and the instrument routing, mute and
+/// solo. Writes the defaults the player used for them.
+fn migrate_v0(session: &mut Map<String, Value>) -> Result<()> {
+    insert_default(session, "bpm", json!(DEFAULT_BPM));
+    insert_default(session, "launch_quantization", json!("Bar"));
+    insert_default(
+        session,
+        "arrangement",
+        json!({"scenes": [], "loop_start": null, "loop_end": null}),
+    );
+    let instruments = session
+        .get_mut("instruments")
+        .and_then(|x| x.as_array_mut())
+        .ok_or_else(|| error("a session needs a list of instruments"))?;
+    for instrument in instruments {
+        let instrument = instrument
+            .as_object_mut()
+            .ok_or_else(|| error("an instrument must be a JSON object"))?;
+        insert_default(instrument, "channel", json!(DEFAULT_CHANNEL));
+        insert_default(instrument, "port", json!(0));
+        insert_default(instrument, "muted", json!(false));
+        insert_default(instrument, "soloed", json!(false));
+    }
+    return Ok(());
+}
+
+/// Version 1 files have no key and no drums, writes the defaults the player
+/// used for them. Patterns without random settings stay as they are.
+fn migrate_v1(session: &mut Map<String, Value>) -> Result<()> {
+    insert_default(session, "root", json!(0));
+    insert_default(session, "scale", serde_json::to_value(default_scale())?);
+    let instruments = session
+        .get_mut("instruments")
+        .and_then(|x| x.as_array_mut())
+        .ok_or_else(|| error("a session needs a list of instruments"))?;
+    for instrument in instruments {
+        let instrument = instrument
+            .as_object_mut()
+            .ok_or_else(|| error("an instrument must be a JSON object"))?;
+        insert_default(instrument, "drum", json!(false));
+        insert_default(instrument, "drums", serde_json::to_value(default_drums())?);
+        insert_default(
+            instrument,
+            "drum_grid",
+            serde_json::to_value(default_drum_grid())?,
+        );
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{DrumGrid, LaunchQuantization, Session};
+    use super::*;
+    use crate::padseq::scale::Scale;
+
+    const V0: &str = include_str!("../../../tests/fixtures/session_v0.json");
+    const V1: &str = include_str!("../../../tests/fixtures/session_v1.json");
+    const V2: &str = include_str!("../../../tests/fixtures/session_v2.json");
+
+    #[test]
+    fn version_0_gets_the_defaults() {
+        let session = Session::from_json(V0).unwrap();
+        assert_eq!(session.format_version, FORMAT_VERSION);
+        assert_eq!(session.get_bpm(), DEFAULT_BPM);
+        assert!(session.get_launch_quantization() == LaunchQuantization::Bar);
+        assert_eq!(session.get_arrangement().len(), 0);
+        assert_eq!(session.get_arrangement().get_loop_start(), None);
+        assert_eq!(session.number_of_instruments(), 2);
+        for index in 0..2 {
+            let instrument = session.get_instrument(index);
+            assert_eq!(instrument.get_channel(), DEFAULT_CHANNEL);
+            assert_eq!(instrument.get_port(), 0);
+            assert!(!instrument.is_muted());
+            assert!(!instrument.is_soloed());
+        }
+        let instrument = session.get_instrument(0);
+        assert_eq!(instrument.get_active_pattern(), Some(0));
+        let pattern = instrument.get_pattern(0).unwrap();
+        assert_eq!(pattern.get_step(0).get(&36), Some(&127));
+        assert_eq!(pattern.get_step(8).get(&38), Some(&100));
+    }
+
+    #[test]
+    fn version_1_gets_the_key_and_drum_defaults() {
+        let session = Session::from_json(V1).unwrap();
+        assert_eq!(session.get_root(), 0);
+        assert!(*session.get_scale() == Scale::Major);
+        let instrument = session.get_instrument(0);
+        assert!(!instrument.is_drum());
+        assert!(instrument.get_drum_grid() == DrumGrid::EightByTwo);
+        assert_eq!(instrument.get_drums().len(), 16);
+        assert_eq!(instrument.get_drums()[0].get_note(), 36);
+        assert!(instrument
+            .get_pattern(1)
+            .unwrap()
+            .get_random_settings()
+            .is_none());
+    }
+
+    #[test]
+    fn version_2_is_read_as_it_is() {
+        let session = Session::from_json(V2).unwrap();
+        assert!(session.validate().is_empty());
+        assert_eq!(session.get_root(), 9);
+        assert!(*session.get_scale() == Scale::Custom(vec![0, 3, 7]));
+        let instrument = session.get_instrument(0);
+        assert!(instrument.is_drum());
+        assert!(instrument.get_drum_grid() == DrumGrid::FourByFour);
+        let drums = instrument.get_drums();
+        assert_eq!(drums.len(), 2);
+        assert_eq!(drums[1].get_name(), "clap");
+        assert_eq!(drums[1].get_note(), 39);
+        let settings = instrument
+            .get_pattern(0)
+            .unwrap()
+            .get_random_settings()
+            .unwrap();
+        assert_eq!(settings.seed, 1234);
+        assert_eq!(settings.notes, vec![36, 39]);
+        assert_eq!(settings.mutations.len(), 1);
+        assert_eq!(settings.mutations[0].amount, 25);
+    }
+
+    #[test]
+    fn version_1_keeps_its_settings() {
+        let session = Session::from_json(V1).unwrap();
+        assert_eq!(session.format_version, FORMAT_VERSION);
+        assert_eq!(session.get_bpm(), 98.0);
+        assert!(session.get_launch_quantization() == LaunchQuantization::Beat);
+        let instrument = session.get_instrument(0);
+        assert_eq!(instrument.get_channel(), 10);
+        assert_eq!(instrument.get_port(), 1);
+        assert!(instrument.is_muted());
+        assert_eq!(instrument.get_active_pattern(), Some(1));
+        assert_eq!(
+            instrument.get_pattern(1).unwrap().get_step(4).get(&42),
+            Some(&80)
+        );
+        let arrangement = session.get_arrangement();
+        assert_eq!(arrangement.len(), 2);
+        assert_eq!(arrangement.get_scene(1).unwrap().get_bars(), 4);
+        assert_eq!(arrangement.get_loop_end(), Some(1));
+    }
+
+    #[test]
+    fn migrated_files_are_written_with_the_current_version() {
+        let session = Session::from_json(V0).unwrap();
+        let value: Value = serde_json::from_str(&session.to_json().unwrap()).unwrap();
+        assert_eq!(value["format_version"], json!(FORMAT_VERSION));
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let json = format!(
+            r#"{{"format_version": {}, "instruments": []}}"#,
+            FORMAT_VERSION + 1
+        );
+        let error = Session::from_json(&json).err().unwrap();
+        assert!(error.to_string().contains("newer"));
+    }
+}
@@ -0,0 +1,485 @@
+use super::session::{
+    Channel, Note, Pattern, Session, Step, StepNotes, Velocity, BAR_SIZE, STEPS_PER_BEAT,
+};
+use std::fs;
+use std::io;
+
+pub const TICKS_PER_BEAT: u16 = 96;
+const TICKS_PER_STEP: u32 = TICKS_PER_BEAT as u32 / STEPS_PER_BEAT as u32;
+
+/// Selects which pattern of each instrument is rendered into its track.
+pub enum PatternSelection {
+    Active,
+    Index(usize),
+}
+
+/// How note-ons between two steps are moved onto the step grid.
+pub enum Quantization {
+    Nearest,
+    Floor,
+}
+
+pub struct ImportOptions {
+    /// Track to take notes from, or all tracks if `None`.
+    pub track: Option<usize>,
+    /// Bar of `BAR_SIZE` steps to take, counted from the start of the file.
+    pub bar: usize,
+    pub quantization: Quantization,
+}
+
+struct NoteOn {
+    tick: u32,
+    note: Note,
+    velocity: Velocity,
+}
+
+struct TrackEvent {
+    tick: u32,
+    // Events on the same tick are written meta first, then note offs, then
+    // note ons, so a retriggered note is released before it starts again.
+    order: u8,
+    data: Vec<u8>,
+}
+
+fn write_variable_length(out: &mut Vec<u8>, value: u32) {
+    let mut buffer = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        buffer.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    buffer.reverse();
+    out.extend(buffer);
+}
+
+fn meta_event(tick: u32, meta_type: u8, payload: &[u8]) -> TrackEvent {
+    let mut data = vec![0xFF, meta_type];
+    write_variable_length(&mut data, payload.len() as u32);
+    data.extend_from_slice(payload);
+    TrackEvent {
+        tick,
+        order: 0,
+        data,
+    }
+}
+
+fn write_track(out: &mut Vec<u8>, mut events: Vec<TrackEvent>, length: u32) {
+    events.sort_by_key(|x| (x.tick, x.order));
+    let mut data = Vec::new();
+    let mut last_tick = 0;
+    for event in events {
+        write_variable_length(&mut data, event.tick - last_tick);
+        data.extend(event.data);
+        last_tick = event.tick;
+    }
+    // end of track, placed at the end of the bar so loops keep their length
+    write_variable_length(&mut data, length.max(last_tick) - last_tick);
+    data.extend([0xFF, 0x2F, 0x00]);
+
+    out.extend(b"MTrk");
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend(data);
+}
+
+fn pattern_events(pattern: &Pattern, channel: Channel) -> Vec<TrackEvent> {
+    let mut events = Vec::new();
+    let status = channel.clamp(1, 16) - 1;
+    for (step, notes) in pattern.get_steps() {
+        let tick = *step as u32 * TICKS_PER_STEP;
+        for (note, velocity) in notes {
+            events.push(TrackEvent {
+                tick,
+                order: 2,
+                data: vec![0x90 + status, *note, *velocity],
+            });
+            events.push(TrackEvent {
+                tick: tick + TICKS_PER_STEP,
+                order: 1,
+                data: vec![0x80 + status, *note, 0],
+            });
+        }
+    }
+    events
+}
+
+/// Renders the session into a type 1 Standard MIDI File.
+///
+/// The first track holds the tempo, followed by one track per instrument.
+/// Every note lasts one step, just as it is played by the sequencer.
+pub fn export_session(session: &Session, selection: &PatternSelection) -> Vec<u8> {
+    let length = BAR_SIZE as u32 * TICKS_PER_STEP;
+    let mut out = Vec::new();
+    out.extend(b"MThd");
+    out.extend(6u32.to_be_bytes());
+    out.extend(1u16.to_be_bytes());
+    out.extend((session.number_of_instruments() as u16 + 1).to_be_bytes());
+    out.extend(TICKS_PER_BEAT.to_be_bytes());
+
+    let tempo = (60_000_000.0 / session.get_bpm()).round() as u32;
+    write_track(
+        &mut out,
+        vec![
+            meta_event(0, 0x03, b"PadSeq"),
+            meta_event(0, 0x51, &tempo.to_be_bytes()[1..]),
+            meta_event(0, 0x58, &[4, 2, 24, 8]),
+        ],
+        length,
+    );
+
+    for index in 0..session.number_of_instruments() {
+        let instrument = session.get_instrument(index);
+        let pattern = match selection {
+            PatternSelection::Active => instrument.get_active_pattern(),
+            PatternSelection::Index(pattern) => Some(*pattern),
+        };
+        let mut events = vec![meta_event(
+            0,
+            0x03,
+            format!("instrument {}", index).as_bytes(),
+        )];
+        if let Some(pattern) = pattern.and_then(|x| instrument.get_pattern(x)) {
+            events.extend(pattern_events(pattern, instrument.get_channel()));
+        }
+        write_track(&mut out, events, length);
+    }
+    out
+}
+
+pub fn write_session(
+    session: &Session,
+    selection: &PatternSelection,
+    path: &str,
+) -> io::Result<()> {
+    fs::write(path, export_session(session, selection))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_at_end(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn read_bytes(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        if self.position + length > self.data.len() {
+            return Err(invalid_data("Unexpected end of MIDI file"));
+        }
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_variable_length(&mut self) -> io::Result<u32> {
+        let mut value: u32 = 0;
+        for _ in 0..4 {
+            let byte = self.read_u8()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid_data("Variable length quantity is too long"))
+    }
+}
+
+fn read_track(data: &[u8]) -> io::Result<Vec<NoteOn>> {
+    let mut reader = Reader { data, position: 0 };
+    let mut notes = Vec::new();
+    let mut tick: u32 = 0;
+    let mut running_status: Option<u8> = None;
+    while !reader.is_at_end() {
+        tick = tick
+            .checked_add(reader.read_variable_length()?)
+            .ok_or_else(|| invalid_data("Track is too long"))?;
+        let mut status = reader.read_u8()?;
+        match status {
+            0xFF => {
+                reader.read_u8()?;
+                let length = reader.read_variable_length()? as usize;
+                reader.read_bytes(length)?;
+                continue;
+            }
+            0xF0 | 0xF7 => {
+                let length = reader.read_variable_length()? as usize;
+                reader.read_bytes(length)?;
+                continue;
+            }
+            0x80..=0xEF => {
+                running_status = Some(status);
+            }
+            0x00..=0x7F => {
+                // data byte, reuse the previous status
+                reader.position -= 1;
+                status = running_status.ok_or_else(|| invalid_data("Missing MIDI status"))?;
+            }
+            _ => {
+                return Err(invalid_data(&format!(
+                    "Unexpected MIDI status {:#x}",
+                    status
+                )));
+            }
+        }
+        match status & 0xF0 {
+            0xC0 | 0xD0 => {
+                reader.read_u8()?;
+            }
+            0x90 => {
+                let note = reader.read_u8()?;
+                let velocity = reader.read_u8()?;
+                if velocity > 0 {
+                    notes.push(NoteOn {
+                        tick,
+                        note,
+                        velocity,
+                    });
+                }
+            }
+            _ => {
+                reader.read_bytes(2)?;
+            }
+        }
+    }
+    Ok(notes)
+}
+
+/// Reads the note-ons of a Standard MIDI File into a single pattern.
+pub fn import_pattern(data: &[u8], options: &ImportOptions) -> io::Result<Pattern> {
+    let mut reader = Reader { data, position: 0 };
+    if reader.read_bytes(4)? != b"MThd" {
+        return Err(invalid_data("Not a Standard MIDI File"));
+    }
+    let header_length = reader.read_u32()? as usize;
+    let header = reader.read_bytes(header_length)?;
+    if header.len() < 6 {
+        return Err(invalid_data("MIDI file header is too short"));
+    }
+    let division = u16::from_be_bytes([header[4], header[5]]);
+    if division & 0x8000 != 0 || division == 0 {
+        return Err(invalid_data("SMPTE timed MIDI files are not supported"));
+    }
+
+    let mut notes = Vec::new();
+    let mut track = 0;
+    while !reader.is_at_end() {
+        let chunk_type = reader.read_bytes(4)?;
+        let length = reader.read_u32()? as usize;
+        let chunk = reader.read_bytes(length)?;
+        if chunk_type != b"MTrk" {
+            continue;
+        }
+        if options.track.is_none() || options.track == Some(track) {
+            notes.extend(read_track(chunk)?);
+        }
+        track += 1;
+    }
+    if let Some(wanted) = options.track {
+        if wanted >= track {
+            return Err(invalid_data(&format!(
+                "The MIDI file has only {} tracks",
+                track
+            )));
+        }
+    }
+
+    let ticks_per_step = division as f64 / STEPS_PER_BEAT as f64;
+    let first_step = options.bar * BAR_SIZE as usize;
+    let mut pattern = Pattern::new();
+    for note in notes {
+        let position = note.tick as f64 / ticks_per_step;
+        let step = match options.quantization {
+            Quantization::Nearest => position.round(),
+            Quantization::Floor => position.floor(),
+        } as usize;
+        if step < first_step || step >= first_step + BAR_SIZE as usize {
+            continue;
+        }
+        let step = (step - first_step) as Step;
+        let mut step_notes = if pattern.has_step_set(step) {
+            pattern.get_step(step).clone()
+        } else {
+            StepNotes::new()
+        };
+        let velocity = step_notes.entry(note.note).or_insert(0);
+        *velocity = (*velocity).max(note.velocity);
+        pattern.set_step(step, &step_notes);
+    }
+    Ok(pattern)
+}
+
+pub fn read_pattern(path: &str, options: &ImportOptions) -> io::Result<Pattern> {
+    import_pattern(&fs::read(path)?, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step_notes(notes: &[(Note, Velocity)]) -> StepNotes {
+        notes.iter().copied().collect()
+    }
+
+    fn options(track: Option<usize>, bar: usize, quantization: Quantization) -> ImportOptions {
+        ImportOptions {
+            track,
+            bar,
+            quantization,
+        }
+    }
+
+    /// A file with one track holding a note-on at each of the ticks.
+    fn file_with_notes(notes: &[(u32, Note)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(b"MThd");
+        out.extend(6u32.to_be_bytes());
+        out.extend(0u16.to_be_bytes());
+        out.extend(1u16.to_be_bytes());
+        out.extend(TICKS_PER_BEAT.to_be_bytes());
+        let events = notes
+            .iter()
+            .map(|(tick, note)| TrackEvent {
+                tick: *tick,
+                order: 2,
+                data: vec![0x90, *note, 100],
+            })
+            .collect();
+        write_track(&mut out, events, 0);
+        out
+    }
+
+    /// The ticks of the note-ons and note-offs of the nth track.
+    fn note_events(data: &[u8], wanted: usize) -> Vec<(u32, u8, Note)> {
+        let mut reader = Reader { data, position: 14 };
+        for _ in 0..wanted {
+            reader.read_bytes(4).unwrap();
+            let length = reader.read_u32().unwrap() as usize;
+            reader.read_bytes(length).unwrap();
+        }
+        reader.read_bytes(4).unwrap();
+        let length = reader.read_u32().unwrap() as usize;
+        let mut track = Reader {
+            data: reader.read_bytes(length).unwrap(),
+            position: 0,
+        };
+        let mut events = Vec::new();
+        let mut tick = 0;
+        while !track.is_at_end() {
+            tick += track.read_variable_length().unwrap();
+            let status = track.read_u8().unwrap();
+            if status == 0xFF {
+                track.read_u8().unwrap();
+                let length = track.read_variable_length().unwrap() as usize;
+                track.read_bytes(length).unwrap();
+            } else {
+                let note = track.read_u8().unwrap();
+                track.read_u8().unwrap();
+                events.push((tick, status & 0xF0, note));
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn exported_patterns_are_imported_again() {
+        let mut session = Session::new(2);
+        let mut drums = Pattern::new();
+        drums.set_step(0, &step_notes(&[(36, 100), (42, 64)]));
+        drums.set_step(4, &step_notes(&[(38, 127)]));
+        drums.set_step(15, &step_notes(&[(42, 1)]));
+        session.get_instrument_mut(0).set_pattern(1, &drums);
+        let mut bass = Pattern::new();
+        bass.set_step(2, &step_notes(&[(40, 90)]));
+        session.get_instrument_mut(1).set_pattern(1, &bass);
+        // only the selected pattern is exported
+        session.get_instrument_mut(1).set_pattern(0, &drums);
+
+        let data = export_session(&session, &PatternSelection::Index(1));
+        for (track, pattern) in [(1, &drums), (2, &bass)] {
+            for quantization in [Quantization::Nearest, Quantization::Floor] {
+                let imported =
+                    import_pattern(&data, &options(Some(track), 0, quantization)).unwrap();
+                assert_eq!(imported.get_steps(), pattern.get_steps());
+            }
+        }
+        let all = import_pattern(&data, &options(None, 0, Quantization::Nearest)).unwrap();
+        assert_eq!(all.get_steps().len(), 4);
+        assert_eq!(all.get_step(2), &step_notes(&[(40, 90)]));
+        let next_bar = import_pattern(&data, &options(Some(1), 1, Quantization::Nearest)).unwrap();
+        assert!(next_bar.get_steps().is_empty());
+        assert!(import_pattern(&data, &options(Some(3), 0, Quantization::Nearest)).is_err());
+    }
+
+    #[test]
+    fn exported_notes_last_one_step() {
+        let mut session = Session::new(1);
+        let mut pattern = Pattern::new();
+        pattern.set_step(3, &step_notes(&[(60, 100)]));
+        pattern.set_step(4, &step_notes(&[(60, 100)]));
+        session.get_instrument_mut(0).set_pattern(0, &pattern);
+        let data = export_session(&session, &PatternSelection::Index(0));
+        let step = TICKS_PER_STEP;
+        // the retriggered note is released before it starts again
+        assert_eq!(
+            note_events(&data, 1),
+            vec![
+                (3 * step, 0x90, 60),
+                (4 * step, 0x80, 60),
+                (4 * step, 0x90, 60),
+                (5 * step, 0x80, 60),
+            ]
+        );
+    }
+
+    #[test]
+    fn notes_are_taken_from_the_selected_bar() {
+        let bar = BAR_SIZE as u32 * TICKS_PER_STEP;
+        let data = file_with_notes(&[(5 * TICKS_PER_STEP, 36), (bar + 2 * TICKS_PER_STEP, 38)]);
+        let first = import_pattern(&data, &options(Some(0), 0, Quantization::Nearest)).unwrap();
+        assert_eq!(first.get_steps().len(), 1);
+        assert_eq!(first.get_step(5), &step_notes(&[(36, 100)]));
+        let second = import_pattern(&data, &options(Some(0), 1, Quantization::Nearest)).unwrap();
+        assert_eq!(second.get_steps().len(), 1);
+        assert_eq!(second.get_step(2), &step_notes(&[(38, 100)]));
+    }
+
+    #[test]
+    fn notes_between_steps_are_quantized() {
+        let late = TICKS_PER_STEP * 2 + TICKS_PER_STEP * 3 / 4;
+        let early = TICKS_PER_STEP * 6 + TICKS_PER_STEP / 4;
+        let data = file_with_notes(&[(late, 36), (early, 38)]);
+        let nearest = import_pattern(&data, &options(None, 0, Quantization::Nearest)).unwrap();
+        assert_eq!(nearest.get_step(3), &step_notes(&[(36, 100)]));
+        assert_eq!(nearest.get_step(6), &step_notes(&[(38, 100)]));
+        let floor = import_pattern(&data, &options(None, 0, Quantization::Floor)).unwrap();
+        assert_eq!(floor.get_step(2), &step_notes(&[(36, 100)]));
+        assert_eq!(floor.get_step(6), &step_notes(&[(38, 100)]));
+        assert_eq!(floor.get_steps().len(), 2);
+    }
+
+    #[test]
+    fn overflowing_delta_times_are_rejected() {
+        let mut track = Vec::new();
+        for _ in 0..20 {
+            // the largest delta time followed by an empty text event
+            write_variable_length(&mut track, 0x0FFF_FFFF);
+            track.extend([0xFF, 0x01, 0x00]);
+        }
+        let error = read_track(&track).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
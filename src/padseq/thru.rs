@@ -0,0 +1,189 @@
+use super::midi::{Instrument, MidiMessageType};
+use super::session::{Channel, Note};
+use serde::{Deserialize, Serialize};
+use serde_json::Result;
+use std::collections::HashMap;
+
+fn default_message_types() -> Vec<MidiMessageType> {
+    vec![
+        MidiMessageType::NoteOff,
+        MidiMessageType::NoteOn,
+        MidiMessageType::PolyAftertouch,
+        MidiMessageType::ControlChange,
+        MidiMessageType::ProgramChange,
+        MidiMessageType::ChannelAftertouch,
+        MidiMessageType::PitchBend,
+    ]
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ThruConfig {
+    /// Input ports whose messages are forwarded.
+    ports: Vec<u8>,
+    /// Message types which are forwarded, all others are dropped.
+    #[serde(default = "default_message_types")]
+    message_types: Vec<MidiMessageType>,
+    /// Maps input channels to output channels. Messages on unmapped channels
+    /// are sent on the channel of the selected instrument.
+    #[serde(default)]
+    channels: HashMap<Channel, Channel>,
+}
+
+impl ThruConfig {
+    pub fn from_json(json: &str) -> Result<ThruConfig> {
+        let c: ThruConfig = serde_json::from_str(json)?;
+        if let Some(problem) = c.validate().into_iter().next() {
+            return Err(serde::de::Error::custom(problem));
+        }
+        Ok(c)
+    }
+
+    /// Checks the ports, message types and channels can be used, returns a
+    /// description of every problem found.
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.ports.is_empty() {
+            problems.push("no input ports are given".to_string());
+        }
+        for (index, port) in self.ports.iter().enumerate() {
+            if self.ports[..index].contains(port) {
+                problems.push(format!("port {} is given twice", port));
+            }
+        }
+        if self.message_types.is_empty() {
+            problems.push("no message types are forwarded".to_string());
+        }
+        for (index, r#type) in self.message_types.iter().enumerate() {
+            if self.message_types[..index].contains(r#type) {
+                problems.push(format!("message type {:?} is given twice", r#type));
+            }
+        }
+        let mut channels: Vec<_> = self.channels.iter().collect();
+        channels.sort();
+        for (from, to) in channels {
+            for channel in [from, to] {
+                if !(1..=16).contains(channel) {
+                    problems.push(format!(
+                        "channel {} of the mapping {} to {} is not within 1-16",
+                        channel, from, to
+                    ));
+                }
+            }
+        }
+        return problems;
+    }
+}
+
+/// Forwards messages from input ports to the selected instrument.
+pub struct ThruRouter {
+    config: ThruConfig,
+    inputs: Vec<Instrument>,
+    // the instrument and channel each forwarded note was sent to, by input,
+    // channel and note
+    held_notes: HashMap<(usize, Channel, Note), (usize, Channel)>,
+}
+
+impl ThruRouter {
+    pub fn new(config: ThruConfig) -> ThruRouter {
+        ThruRouter {
+            config: config,
+            inputs: Vec::new(),
+            held_notes: HashMap::new(),
+        }
+    }
+
+    pub fn connect(&mut self) {
+        for port in &self.config.ports {
+            let mut input = Instrument::new(&format!("thru {}", port));
+            input.connect_in(*port);
+            self.inputs.push(input);
+        }
+    }
+
+    /// Sends all pending input messages to the selected instrument, given by
+    /// its index and channel, or drops them if none is selected. Notes are
+    /// released on the instrument and channel they were sent to, even if the
+    /// selection changed in between.
+    pub fn route(&mut self, instruments: &mut [Instrument], selected: Option<(usize, Channel)>) {
+        for (input_index, input) in self.inputs.iter_mut().enumerate() {
+            while let Some(event) = input.pop_event() {
+                let mut message = event.message;
+                let key = (input_index, message.channel, message.note);
+                let is_note_off = message.r#type == MidiMessageType::NoteOff
+                    || (message.r#type == MidiMessageType::NoteOn && message.velocity == 0);
+                if is_note_off {
+                    if let Some((instrument, channel)) = self.held_notes.remove(&key) {
+                        if let Some(instrument) = instruments.get_mut(instrument) {
+                            message.channel = channel;
+                            instrument.send_message(message);
+                        }
+                        continue;
+                    }
+                }
+                if !self.config.message_types.contains(&message.r#type) {
+                    continue;
+                }
+                let (instrument, channel) = match selected {
+                    Some(selected) => selected,
+                    None => continue,
+                };
+                message.channel = *self
+                    .config
+                    .channels
+                    .get(&message.channel)
+                    .unwrap_or(&channel);
+                if message.r#type == MidiMessageType::NoteOn && !is_note_off {
+                    let previous = self.held_notes.insert(key, (instrument, message.channel));
+                    // a note played again before it was released
+                    if let Some((other, other_channel)) = previous {
+                        if (other, other_channel) != (instrument, message.channel) {
+                            if let Some(other) = instruments.get_mut(other) {
+                                other.stop_note(other_channel, message.note);
+                            }
+                        }
+                    }
+                }
+                instruments[instrument].send_message(message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_configs_are_read() {
+        let config =
+            ThruConfig::from_json(r#"{"ports": [1, 2], "channels": {"1": 10, "16": 1}}"#).unwrap();
+        assert_eq!(config.ports, vec![1, 2]);
+        assert_eq!(config.channels[&1], 10);
+        assert_eq!(config.message_types, default_message_types());
+    }
+
+    #[test]
+    fn channels_outside_of_the_midi_range_are_rejected() {
+        for json in [
+            r#"{"ports": [1], "channels": {"0": 1}}"#,
+            r#"{"ports": [1], "channels": {"1": 0}}"#,
+            r#"{"ports": [1], "channels": {"17": 1}}"#,
+            r#"{"ports": [1], "channels": {"1": 200}}"#,
+        ] {
+            assert!(ThruConfig::from_json(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn unusable_ports_and_message_types_are_rejected() {
+        for json in [
+            r#"{"ports": []}"#,
+            r#"{"ports": [1, 1]}"#,
+            r#"{"ports": [1], "message_types": []}"#,
+            r#"{"ports": [1], "message_types": ["NoteOn", "NoteOn"]}"#,
+            r#"{"ports": [1], "message_types": ["SysEx"]}"#,
+        ] {
+            assert!(ThruConfig::from_json(json).is_err(), "{}", json);
+        }
+    }
+}
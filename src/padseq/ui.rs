@@ -3,15 +3,44 @@ pub mod screens;
 use super::midi::Instrument;
 use super::sequencer::{Sequencer, WaitResult};
 use crate::padseq::session::{Note, Pattern as SessionPattern};
+use screens::arrangement::Arrangement;
+use screens::euclid::Euclid;
+use screens::library::Library;
+use screens::mixer::Mixer;
 use screens::pattern::Pattern;
+use screens::projects::Projects;
+use screens::random::RandomGenerator;
 use screens::session::Session;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread::sleep;
 use std::time::Duration;
 
+/// Releasing one of these buttons undoes or redoes the last edit.
+const PAD_UNDO_CC: u8 = 97;
+const PAD_REDO_CC: u8 = 98;
+const PAD_HISTORY_CCS: [u8; 2] = [PAD_UNDO_CC, PAD_REDO_CC];
+/// Holding both of these buttons silences all instruments. The session and
+/// layout buttons only change what is shown, so the combo never edits the
+/// session.
+const PAD_PANIC_CCS: [u8; 2] = [95, 96];
+const PAD_COLOR_HISTORY: u8 = 9;
+
 pub enum ScreenEvent {
     None,
     SwitchToPattern(usize, usize),
     SwitchToSession,
+    SwitchToMixer,
+    SwitchToArrangement,
+    SwitchToProjects,
+    SwitchToLibrary,
+    /// Generates an Euclidean rhythm of the notes in the pattern.
+    SwitchToEuclid(usize, usize, Vec<Note>),
+    /// Generates a random pattern from the held keys or a scale from the
+    /// root note of the session key.
+    SwitchToRandom(usize, usize, Vec<Note>, Note),
+    /// Back to the session screen to paste the pattern into a slot.
+    PasteToSession(SessionPattern),
 }
 
 pub struct UIContext<'a> {
@@ -40,15 +69,174 @@ pub struct UI {
     sequencer: Sequencer,
     pad: Instrument,
     screen: Box<dyn Screen>,
+    panic_pressed: bool,
+    history_button: Option<u8>,
 }
 
 impl UI {
     pub fn new(sequencer: Sequencer) -> UI {
+        let mut pad = Instrument::with_scheduler("Pad", sequencer.get_scheduler());
+        // the screens only handle pad presses and buttons, not pressure
+        pad.set_buttons_only(true);
         UI {
+            pad: pad,
             sequencer: sequencer,
-            pad: Instrument::new(&"Pad".to_string()),
             screen: Box::new(Session::new()),
+            panic_pressed: false,
+            history_button: None,
+        }
+    }
+
+    fn handle_panic_combo(&mut self) {
+        let pressed = PAD_PANIC_CCS.iter().all(|cc| self.pad.is_control_held(*cc));
+        if pressed && !self.panic_pressed {
+            println!("panic");
+            self.sequencer.panic();
+        }
+        self.panic_pressed = pressed;
+    }
+
+    /// Undoes or redoes once the button is released.
+    fn handle_history_buttons(&mut self) -> ScreenEvent {
+        match self.history_button {
+            None => {
+                self.history_button = PAD_HISTORY_CCS
+                    .iter()
+                    .copied()
+                    .find(|cc| self.pad.is_control_held(*cc));
+            }
+            Some(cc) if !self.pad.is_control_held(cc) => {
+                self.history_button = None;
+                if cc == PAD_UNDO_CC {
+                    self.sequencer.undo();
+                } else {
+                    self.sequencer.redo();
+                }
+                return self.check_selected();
+            }
+            Some(_) => {}
         }
+        return ScreenEvent::None;
+    }
+
+    /// Leaves the pattern screen if its pattern was undone or the project
+    /// switched.
+    fn check_selected(&mut self) -> ScreenEvent {
+        match self.sequencer.get_selected() {
+            None => ScreenEvent::None,
+            Some((instrument, pattern)) => {
+                let session = self.sequencer.get_session();
+                if instrument < session.number_of_instruments()
+                    && session.get_instrument(instrument).has_pattern(pattern)
+                {
+                    ScreenEvent::None
+                } else {
+                    ScreenEvent::SwitchToSession
+                }
+            }
+        }
+    }
+
+    fn refresh_history_buttons(&mut self) {
+        let undo_color = if self.sequencer.can_undo() {
+            PAD_COLOR_HISTORY
+        } else {
+            0
+        };
+        self.pad.send_cc(1, PAD_UNDO_CC, undo_color);
+        let redo_color = if self.sequencer.can_redo() {
+            PAD_COLOR_HISTORY
+        } else {
+            0
+        };
+        self.pad.send_cc(1, PAD_REDO_CC, redo_color);
+    }
+
+    fn handle_screen_event(&mut self, event: ScreenEvent) {
+        match event {
+            ScreenEvent::SwitchToSession => {
+                self.sequencer.set_selected(None);
+                self.screen.clear(create_context!(self));
+                self.screen = Box::new(Session::new());
+            }
+            ScreenEvent::SwitchToMixer => {
+                self.sequencer.set_selected(None);
+                self.screen.clear(create_context!(self));
+                self.screen = Box::new(Mixer::new());
+            }
+            ScreenEvent::SwitchToArrangement => {
+                self.sequencer.set_selected(None);
+                self.screen.clear(create_context!(self));
+                self.screen = Box::new(Arrangement::new());
+            }
+            ScreenEvent::SwitchToProjects => {
+                self.sequencer.set_selected(None);
+                self.screen.clear(create_context!(self));
+                self.screen = Box::new(Projects::new());
+            }
+            ScreenEvent::SwitchToLibrary => {
+                self.sequencer.set_selected(None);
+                self.screen.clear(create_context!(self));
+                self.screen = Box::new(Library::new());
+            }
+            ScreenEvent::SwitchToEuclid(instrument, pattern, notes) => {
+                self.screen.clear(create_context!(self));
+                self.screen = Box::new(Euclid::new(instrument, pattern, notes));
+            }
+            ScreenEvent::SwitchToRandom(instrument, pattern, held_keys, root) => {
+                // continue from the settings the pattern was generated with
+                let settings = self
+                    .sequencer
+                    .get_session()
+                    .get_instrument(instrument)
+                    .get_pattern(pattern)
+                    .and_then(|x| x.get_random_settings())
+                    .cloned();
+                self.screen.clear(create_context!(self));
+                let scale = self.sequencer.get_session().get_scale().clone();
+                self.screen = Box::new(RandomGenerator::new(
+                    instrument, pattern, held_keys, root, scale, settings,
+                ));
+            }
+            ScreenEvent::PasteToSession(pattern) => {
+                self.sequencer.set_selected(None);
+                self.screen.clear(create_context!(self));
+                self.screen = Box::new(Session::with_pattern_to_paste(pattern));
+            }
+            ScreenEvent::SwitchToPattern(instrument, pattern) => {
+                println!("switch to {} {}", instrument, pattern);
+                if instrument >= self.sequencer.get_session().number_of_instruments() {
+                    return;
+                }
+                if !self
+                    .sequencer
+                    .get_session()
+                    .get_instrument(instrument)
+                    .has_pattern(pattern)
+                {
+                    self.sequencer
+                        .get_session_mut()
+                        .get_instrument_mut(instrument)
+                        .set_pattern(pattern, &SessionPattern::new());
+                }
+                self.sequencer.set_selected(Some((instrument, pattern)));
+                self.screen.clear(create_context!(self));
+                self.screen = Box::new(Pattern::new(instrument, pattern));
+            }
+            ScreenEvent::None => {}
+        }
+    }
+
+    fn shutdown(&mut self) {
+        println!("shutting down");
+        self.sequencer.panic();
+        self.screen.clear(create_context!(self));
+        for cc in PAD_HISTORY_CCS {
+            self.pad.send_cc(1, cc, 0);
+        }
+        self.pad.send_events();
+        self.sequencer.save_session();
+        self.sequencer.flush_session();
     }
 
     pub fn run(&mut self) {
@@ -57,44 +245,34 @@ impl UI {
         self.pad.connect_in(2);
         self.sequencer.connect();
         print!("Connect done");
-        loop {
+        let running = Arc::new(AtomicBool::new(true));
+        let handler_running = running.clone();
+        ctrlc::set_handler(move || handler_running.store(false, Ordering::SeqCst))
+            .expect("Unable to set signal handler");
+        while running.load(Ordering::SeqCst) {
             match self.sequencer.wait() {
                 WaitResult::Step => {
                     self.screen.prepare_step(create_context!(self));
                     let played_notes = self.sequencer.process_step();
+                    let event = self.check_selected();
+                    self.handle_screen_event(event);
                     for (instrument, note) in played_notes {
                         self.screen
                             .on_played_note(create_context!(self), instrument, note);
                     }
                     self.screen.refresh(create_context!(self));
+                    self.refresh_history_buttons();
                 }
                 WaitResult::Intermediate => {
-                    match self.screen.handle_pad_events(create_context!(self)) {
-                        ScreenEvent::SwitchToSession => {
-                            self.screen.clear(create_context!(self));
-                            self.screen = Box::new(Session::new());
-                        }
-                        ScreenEvent::SwitchToPattern(instrument, pattern) => {
-                            println!("switch to {} {}", instrument, pattern);
-                            if !self
-                                .sequencer
-                                .get_session()
-                                .get_instrument(instrument)
-                                .has_pattern(pattern)
-                            {
-                                self.sequencer
-                                    .get_session_mut()
-                                    .get_instrument_mut(instrument)
-                                    .set_pattern(pattern, &SessionPattern::new());
-                            }
-                            self.screen.clear(create_context!(self));
-                            self.screen = Box::new(Pattern::new(instrument, pattern));
-                        }
-                        ScreenEvent::None => {}
-                    }
+                    let event = self.screen.handle_pad_events(create_context!(self));
+                    self.handle_screen_event(event);
+                    self.handle_panic_combo();
+                    let event = self.handle_history_buttons();
+                    self.handle_screen_event(event);
                 }
             }
             sleep(Duration::from_micros(1));
         }
+        self.shutdown();
     }
 }
@@ -1,2 +1,8 @@
+pub mod arrangement;
+pub mod euclid;
+pub mod library;
+pub mod mixer;
 pub mod pattern;
+pub mod projects;
+pub mod random;
 pub mod session;
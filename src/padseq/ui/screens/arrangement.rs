@@ -0,0 +1,241 @@
+use super::pattern::PAD_BAR_NOTES;
+
+use crate::padseq::midi::MidiMessageType;
+use crate::padseq::ui::{Screen, ScreenEvent, UIContext};
+
+const PAD_BARS_NOTES: [u8; 8] = [41, 42, 43, 44, 45, 46, 47, 48];
+const PAD_CAPTURE_BUTTON_NOTE: u8 = 11;
+const PAD_DELETE_BUTTON_NOTE: u8 = 12;
+const PAD_LOOP_START_BUTTON_NOTE: u8 = 13;
+const PAD_LOOP_END_BUTTON_NOTE: u8 = 14;
+const PAD_PLAY_BUTTON_NOTE: u8 = 18;
+const PAD_PREV_CC: u8 = 93;
+const PAD_NEXT_CC: u8 = 94;
+const PAD_SESSION_CC: u8 = 95;
+const PAD_COLOR_SCENE: u8 = 45;
+const PAD_COLOR_SCENE_LOOPED: u8 = 37;
+const PAD_COLOR_SCENE_SELECTED: u8 = 13;
+const PAD_COLOR_BARS: u8 = 21;
+const PAD_COLOR_BARS_UNSET: u8 = 1;
+
+/// Builds the arrangement from the active patterns and plays it.
+///
+/// Every step pad is a scene of the chain. The fourth row sets the number of
+/// bars of the selected scene, the buttons below capture the active patterns
+/// as a new scene after the selected one, delete it, set the loop points and
+/// start or stop the song. The arrow buttons step through the chain.
+pub struct Arrangement {
+    selected: Option<usize>,
+}
+
+impl Arrangement {
+    pub fn new() -> Arrangement {
+        Arrangement { selected: None }
+    }
+
+    fn number_of_scenes(&self, context: &mut UIContext) -> usize {
+        return context.sequencer.get_session().get_arrangement().len();
+    }
+
+    fn select(&mut self, scene: usize, context: &mut UIContext) {
+        if scene < self.number_of_scenes(context) {
+            self.selected = Some(scene);
+            if context.sequencer.get_song_scene().is_some() {
+                context.sequencer.play_song(scene);
+            }
+        }
+    }
+
+    fn refresh_scene(&mut self, scene: usize, context: &mut UIContext) {
+        let note = PAD_BAR_NOTES[scene];
+        let arrangement = context.sequencer.get_session().get_arrangement();
+        if scene >= arrangement.len() {
+            context.pad.play_note(1, note, 0, 0.0);
+            return;
+        }
+        let color = if self.selected == Some(scene) {
+            PAD_COLOR_SCENE_SELECTED
+        } else if arrangement.is_looped(scene) {
+            PAD_COLOR_SCENE_LOOPED
+        } else {
+            PAD_COLOR_SCENE
+        };
+        let channel = if context.sequencer.get_song_scene() == Some(scene) {
+            3
+        } else {
+            1
+        };
+        context.pad.play_note(channel, note, color, 0.0);
+    }
+
+    fn handle_button(&mut self, note: u8, context: &mut UIContext) {
+        let selected = self.selected;
+        let session = context.sequencer.get_session_mut();
+        match note {
+            PAD_CAPTURE_BUTTON_NOTE => {
+                let index = selected.map(|x| x + 1).unwrap_or(0);
+                let scene = session.capture_scene(1);
+                session.get_arrangement_mut().insert_scene(index, scene);
+                self.selected = Some(index);
+            }
+            PAD_DELETE_BUTTON_NOTE => {
+                if let Some(index) = selected {
+                    session.get_arrangement_mut().remove_scene(index);
+                    let len = session.get_arrangement().len();
+                    self.selected = if len > 0 {
+                        Some(index.min(len - 1))
+                    } else {
+                        None
+                    };
+                }
+            }
+            PAD_LOOP_START_BUTTON_NOTE => {
+                let arrangement = session.get_arrangement_mut();
+                let start = if arrangement.get_loop_start() == selected {
+                    None
+                } else {
+                    selected
+                };
+                arrangement.set_loop_start(start);
+            }
+            PAD_LOOP_END_BUTTON_NOTE => {
+                let arrangement = session.get_arrangement_mut();
+                let end = if arrangement.get_loop_end() == selected {
+                    None
+                } else {
+                    selected
+                };
+                arrangement.set_loop_end(end);
+            }
+            PAD_PLAY_BUTTON_NOTE => {
+                if context.sequencer.get_song_scene().is_some() {
+                    context.sequencer.stop_song();
+                } else {
+                    context.sequencer.play_song(selected.unwrap_or(0));
+                }
+                return;
+            }
+            _ => {
+                if let Some(bars) = PAD_BARS_NOTES.iter().position(|&x| x == note) {
+                    if let Some(scene) =
+                        selected.and_then(|x| session.get_arrangement_mut().get_scene_mut(x))
+                    {
+                        scene.set_bars(bars as u8 + 1);
+                    }
+                } else {
+                    return;
+                }
+            }
+        }
+        context.sequencer.save_session();
+    }
+}
+
+impl Screen for Arrangement {
+    fn handle_pad_events(&mut self, context: &mut UIContext) -> ScreenEvent {
+        while context.pad.has_events() {
+            let event = context.pad.pop_event().unwrap();
+            let message = event.message;
+            if message.velocity == 0 {
+                continue;
+            }
+            match message.r#type {
+                MidiMessageType::ControlChange => match message.note {
+                    PAD_SESSION_CC => {
+                        return ScreenEvent::SwitchToSession;
+                    }
+                    PAD_PREV_CC => {
+                        let scene = self
+                            .selected
+                            .or(context.sequencer.get_song_scene())
+                            .unwrap_or(0);
+                        self.select(scene.saturating_sub(1), context);
+                    }
+                    PAD_NEXT_CC => {
+                        let scene = match self.selected.or(context.sequencer.get_song_scene()) {
+                            Some(scene) => scene + 1,
+                            None => 0,
+                        };
+                        self.select(scene, context);
+                    }
+                    _ => {}
+                },
+                MidiMessageType::NoteOn => {
+                    match PAD_BAR_NOTES.iter().position(|&x| x == message.note) {
+                        Some(scene) => {
+                            if scene < self.number_of_scenes(context) {
+                                self.selected = Some(scene);
+                            }
+                        }
+                        None => self.handle_button(message.note, context),
+                    }
+                }
+                _ => {}
+            }
+        }
+        context.pad.send_events();
+        return ScreenEvent::None;
+    }
+
+    fn refresh(&mut self, context: &mut UIContext) {
+        context.pad.send_cc(1, PAD_SESSION_CC, 41);
+        context.pad.send_cc(1, PAD_PREV_CC, 41);
+        context.pad.send_cc(1, PAD_NEXT_CC, 41);
+        for scene in 0..PAD_BAR_NOTES.len() {
+            self.refresh_scene(scene, context);
+        }
+        let bars = self
+            .selected
+            .and_then(|x| {
+                context
+                    .sequencer
+                    .get_session()
+                    .get_arrangement()
+                    .get_scene(x)
+            })
+            .map(|x| x.get_bars())
+            .unwrap_or(0);
+        for (index, note) in PAD_BARS_NOTES.iter().enumerate() {
+            let color = if (index as u8) < bars {
+                PAD_COLOR_BARS
+            } else {
+                PAD_COLOR_BARS_UNSET
+            };
+            context.pad.play_note(1, *note, color, 0.0);
+        }
+        context.pad.play_note(1, PAD_CAPTURE_BUTTON_NOTE, 21, 0.0);
+        context.pad.play_note(1, PAD_DELETE_BUTTON_NOTE, 5, 0.0);
+        context
+            .pad
+            .play_note(1, PAD_LOOP_START_BUTTON_NOTE, 37, 0.0);
+        context.pad.play_note(1, PAD_LOOP_END_BUTTON_NOTE, 37, 0.0);
+        context.pad.play_note(
+            if context.sequencer.get_song_scene().is_some() {
+                3
+            } else {
+                1
+            },
+            PAD_PLAY_BUTTON_NOTE,
+            21,
+            0.0,
+        );
+    }
+
+    fn clear(&mut self, context: &mut UIContext) {
+        for note in PAD_BAR_NOTES.iter().chain(PAD_BARS_NOTES.iter()) {
+            context.pad.play_note(1, *note, 0, 0.0);
+        }
+        for note in [
+            PAD_CAPTURE_BUTTON_NOTE,
+            PAD_DELETE_BUTTON_NOTE,
+            PAD_LOOP_START_BUTTON_NOTE,
+            PAD_LOOP_END_BUTTON_NOTE,
+            PAD_PLAY_BUTTON_NOTE,
+        ] {
+            context.pad.play_note(1, note, 0, 0.0);
+        }
+        for cc in [PAD_SESSION_CC, PAD_PREV_CC, PAD_NEXT_CC] {
+            context.pad.send_cc(1, cc, 0);
+        }
+    }
+}
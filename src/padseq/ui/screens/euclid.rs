@@ -0,0 +1,210 @@
+use super::pattern::PAD_BAR_NOTES;
+
+use crate::padseq::generators::{euclidean, fill_rhythm};
+use crate::padseq::midi::MidiMessageType;
+use crate::padseq::session::{Note, Pattern as SessionPattern, Step, BAR_SIZE};
+use crate::padseq::ui::{Screen, ScreenEvent, UIContext};
+
+/// Number of hits, from one or seventeen on.
+const PAD_HITS_NOTES: [u8; 16] = [
+    41, 42, 43, 44, 45, 46, 47, 48, 31, 32, 33, 34, 35, 36, 37, 38,
+];
+/// Rotation of the hits, from zero or sixteen on.
+const PAD_ROTATION_NOTES: [u8; 16] = [
+    21, 22, 23, 24, 25, 26, 27, 28, 11, 12, 13, 14, 15, 16, 17, 18,
+];
+const PAD_COMMIT_CC: u8 = 89;
+const PAD_CANCEL_CC: u8 = 79;
+const PAD_UPPER_RANGE_CC: u8 = 69;
+const PAD_COLOR_HIT: u8 = 21;
+const PAD_COLOR_HIT_REPEATED: u8 = 23;
+const PAD_COLOR_REST: u8 = 1;
+const PAD_COLOR_VALUE: u8 = 45;
+const PAD_COLOR_VALUE_UNSET: u8 = 1;
+const PAD_COLOR_COMMIT: u8 = 21;
+const PAD_COLOR_CANCEL: u8 = 5;
+const PAD_COLOR_RANGE: u8 = 1;
+const PAD_COLOR_UPPER_RANGE: u8 = 45;
+const VELOCITY: u8 = 127;
+
+/// Fills the selected notes of a pattern with an Euclidean rhythm.
+///
+/// The step pads show the result, pressing one makes the rhythm that many
+/// steps long. The two rows below set the number of hits and the two lowest
+/// rows the rotation. Rhythms longer than sixteen steps light the third side
+/// button, which switches those rows to the values from sixteen on. The
+/// pattern plays the result until it is committed or cancelled with the side
+/// buttons.
+pub struct Euclid {
+    instrument: usize,
+    pattern: usize,
+    notes: Vec<Note>,
+    hits: Step,
+    steps: Step,
+    rotation: Step,
+    // the hits and rotation pads show the values from sixteen on
+    upper_range: bool,
+}
+
+impl Euclid {
+    pub fn new(instrument: usize, pattern: usize, notes: Vec<Note>) -> Euclid {
+        Euclid {
+            instrument: instrument,
+            pattern: pattern,
+            notes: notes,
+            hits: 4,
+            steps: 16,
+            rotation: 0,
+            upper_range: false,
+        }
+    }
+
+    /// The pattern filled with the rhythm, `None` if the pattern is gone
+    /// after an undo or a project switch.
+    fn generate(&self, context: &mut UIContext) -> Option<SessionPattern> {
+        let session = context.sequencer.get_session();
+        if self.instrument >= session.number_of_instruments() {
+            return None;
+        }
+        let the_pattern = session
+            .get_instrument(self.instrument)
+            .get_pattern(self.pattern)?;
+        let rhythm = euclidean(self.hits, self.steps, self.rotation);
+        return Some(fill_rhythm(the_pattern, &self.notes, VELOCITY, &rhythm));
+    }
+
+    fn update_preview(&mut self, context: &mut UIContext) {
+        let preview = self
+            .generate(context)
+            .map(|x| (self.instrument, self.pattern, x));
+        context.sequencer.set_preview(preview);
+    }
+
+    /// The value of the first hits and rotation pads.
+    fn get_range_start(&self) -> Step {
+        return if self.upper_range { 16 } else { 0 };
+    }
+}
+
+impl Screen for Euclid {
+    fn handle_pad_events(&mut self, context: &mut UIContext) -> ScreenEvent {
+        if self.generate(context).is_none() {
+            return ScreenEvent::SwitchToSession;
+        }
+        while context.pad.has_events() {
+            let event = context.pad.pop_event().unwrap();
+            let message = event.message;
+            if message.velocity == 0 {
+                continue;
+            }
+            let note = message.note;
+            match message.r#type {
+                MidiMessageType::ControlChange => match note {
+                    PAD_COMMIT_CC => {
+                        let the_pattern = match self.generate(context) {
+                            Some(the_pattern) => the_pattern,
+                            None => return ScreenEvent::SwitchToSession,
+                        };
+                        context
+                            .sequencer
+                            .set_pattern(self.instrument, self.pattern, &the_pattern);
+                        return ScreenEvent::SwitchToPattern(self.instrument, self.pattern);
+                    }
+                    PAD_CANCEL_CC => {
+                        return ScreenEvent::SwitchToPattern(self.instrument, self.pattern);
+                    }
+                    PAD_UPPER_RANGE_CC => {
+                        self.upper_range = !self.upper_range;
+                    }
+                    _ => {}
+                },
+                MidiMessageType::NoteOn => {
+                    if let Some(step) = PAD_BAR_NOTES.iter().position(|&x| x == note) {
+                        self.steps = step as Step + 1;
+                    } else if let Some(hits) = PAD_HITS_NOTES.iter().position(|&x| x == note) {
+                        self.hits = self.get_range_start() + hits as Step + 1;
+                    } else if let Some(rotation) =
+                        PAD_ROTATION_NOTES.iter().position(|&x| x == note)
+                    {
+                        self.rotation = self.get_range_start() + rotation as Step;
+                    } else {
+                        continue;
+                    }
+                    self.update_preview(context);
+                }
+                _ => {}
+            }
+        }
+        context.pad.send_events();
+        return ScreenEvent::None;
+    }
+
+    fn refresh(&mut self, context: &mut UIContext) {
+        // follows changes of the other notes, like recordings
+        self.update_preview(context);
+        let rhythm = euclidean(self.hits, self.steps, self.rotation);
+        for step in 0..BAR_SIZE {
+            let hit = rhythm[step as usize % rhythm.len()];
+            let color = match (hit, step < self.steps) {
+                (true, true) => PAD_COLOR_HIT,
+                (true, false) => PAD_COLOR_HIT_REPEATED,
+                (false, true) => PAD_COLOR_REST,
+                (false, false) => 0,
+            };
+            let channel = if step == context.sequencer.get_active_step() {
+                3
+            } else {
+                1
+            };
+            context
+                .pad
+                .play_note(channel, PAD_BAR_NOTES[step as usize], color, 0.0);
+        }
+        for (index, note) in PAD_HITS_NOTES.iter().enumerate() {
+            let hits = self.get_range_start() + index as Step + 1;
+            let color = if hits > self.steps {
+                0
+            } else if hits <= self.hits {
+                PAD_COLOR_VALUE
+            } else {
+                PAD_COLOR_VALUE_UNSET
+            };
+            context.pad.play_note(1, *note, color, 0.0);
+        }
+        for (index, note) in PAD_ROTATION_NOTES.iter().enumerate() {
+            let rotation = self.get_range_start() + index as Step;
+            let color = if rotation >= self.steps {
+                0
+            } else if rotation == self.rotation {
+                PAD_COLOR_VALUE
+            } else {
+                PAD_COLOR_VALUE_UNSET
+            };
+            context.pad.play_note(1, *note, color, 0.0);
+        }
+        context.pad.send_cc(1, PAD_COMMIT_CC, PAD_COLOR_COMMIT);
+        context.pad.send_cc(1, PAD_CANCEL_CC, PAD_COLOR_CANCEL);
+        let range_color = if self.upper_range {
+            PAD_COLOR_UPPER_RANGE
+        } else if self.steps > 16 {
+            PAD_COLOR_RANGE
+        } else {
+            0
+        };
+        context.pad.send_cc(1, PAD_UPPER_RANGE_CC, range_color);
+    }
+
+    fn clear(&mut self, context: &mut UIContext) {
+        context.sequencer.set_preview(None);
+        for note in PAD_BAR_NOTES
+            .iter()
+            .chain(PAD_HITS_NOTES.iter())
+            .chain(PAD_ROTATION_NOTES.iter())
+        {
+            context.pad.play_note(1, *note, 0, 0.0);
+        }
+        context.pad.send_cc(1, PAD_COMMIT_CC, 0);
+        context.pad.send_cc(1, PAD_CANCEL_CC, 0);
+        context.pad.send_cc(1, PAD_UPPER_RANGE_CC, 0);
+    }
+}
@@ -0,0 +1,137 @@
+use super::pattern::PAD_BAR_NOTES;
+
+use crate::padseq::midi::MidiMessageType;
+use crate::padseq::session::Step;
+use crate::padseq::ui::{Screen, ScreenEvent, UIContext};
+
+/// The lower half shows the steps of the selected pattern.
+const PAD_PREVIEW_NOTES: [u8; 32] = [
+    41, 42, 43, 44, 45, 46, 47, 48, 31, 32, 33, 34, 35, 36, 37, 38, 21, 22, 23, 24, 25, 26, 27, 28,
+    11, 12, 13, 14, 15, 16, 17, 18,
+];
+const PAD_UP_CC: u8 = 91;
+const PAD_DOWN_CC: u8 = 92;
+const PAD_SESSION_CC: u8 = 95;
+const PAD_COLOR_ARROW: u8 = 41;
+const PAD_COLOR_PRESET: u8 = 45;
+const PAD_COLOR_FILE: u8 = 53;
+const PAD_COLOR_SELECTED: u8 = 13;
+const PAD_COLOR_PREVIEW_STEP: u8 = 21;
+
+/// Browses the pattern library, one pad per pattern on the upper half.
+///
+/// Pressing a pattern shows its steps on the lower half, pressing it again
+/// goes back to the session screen to paste it into a slot. The arrow
+/// buttons page through the library.
+pub struct Library {
+    page: usize,
+    selected: Option<usize>,
+}
+
+impl Library {
+    pub fn new() -> Library {
+        Library {
+            page: 0,
+            selected: None,
+        }
+    }
+
+    fn number_of_pages(&self, context: &mut UIContext) -> usize {
+        let count = context.sequencer.get_pattern_library().len();
+        return count.div_ceil(PAD_BAR_NOTES.len()).max(1);
+    }
+
+    fn refresh_entries(&mut self, context: &mut UIContext) {
+        let library = context.sequencer.get_pattern_library();
+        for (slot, note) in PAD_BAR_NOTES.iter().enumerate() {
+            let index = self.page * PAD_BAR_NOTES.len() + slot;
+            let color = match library.get_entry(index) {
+                None => 0,
+                Some(_) if self.selected == Some(index) => PAD_COLOR_SELECTED,
+                Some(entry) if entry.is_builtin() => PAD_COLOR_PRESET,
+                Some(_) => PAD_COLOR_FILE,
+            };
+            context.pad.play_note(1, *note, color, 0.0);
+        }
+    }
+
+    fn refresh_preview(&mut self, context: &mut UIContext) {
+        let entry = self
+            .selected
+            .and_then(|x| context.sequencer.get_pattern_library().get_entry(x));
+        for (step, note) in PAD_PREVIEW_NOTES.iter().enumerate() {
+            let color = match entry {
+                Some(entry) if entry.get_pattern().has_step_set(step as Step) => {
+                    PAD_COLOR_PREVIEW_STEP
+                }
+                _ => 0,
+            };
+            context.pad.play_note(1, *note, color, 0.0);
+        }
+    }
+}
+
+impl Screen for Library {
+    fn handle_pad_events(&mut self, context: &mut UIContext) -> ScreenEvent {
+        while context.pad.has_events() {
+            let event = context.pad.pop_event().unwrap();
+            let message = event.message;
+            if message.velocity == 0 {
+                continue;
+            }
+            match message.r#type {
+                MidiMessageType::ControlChange => match message.note {
+                    PAD_SESSION_CC => {
+                        return ScreenEvent::SwitchToSession;
+                    }
+                    PAD_UP_CC => {
+                        self.page = self.page.saturating_sub(1);
+                    }
+                    PAD_DOWN_CC if self.page + 1 < self.number_of_pages(context) => {
+                        self.page += 1;
+                    }
+                    _ => {}
+                },
+                MidiMessageType::NoteOn => {
+                    if let Some(slot) = PAD_BAR_NOTES.iter().position(|&x| x == message.note) {
+                        let index = self.page * PAD_BAR_NOTES.len() + slot;
+                        let library = context.sequencer.get_pattern_library();
+                        if let Some(entry) = library.get_entry(index) {
+                            if self.selected == Some(index) {
+                                return ScreenEvent::PasteToSession(entry.get_pattern().clone());
+                            }
+                            println!("library pattern {}", entry.get_name());
+                            self.selected = Some(index);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        context.pad.send_events();
+        return ScreenEvent::None;
+    }
+
+    fn refresh(&mut self, context: &mut UIContext) {
+        context.pad.send_cc(1, PAD_SESSION_CC, 41);
+        let up_color = if self.page > 0 { PAD_COLOR_ARROW } else { 0 };
+        context.pad.send_cc(1, PAD_UP_CC, up_color);
+        let down_color = if self.page + 1 < self.number_of_pages(context) {
+            PAD_COLOR_ARROW
+        } else {
+            0
+        };
+        context.pad.send_cc(1, PAD_DOWN_CC, down_color);
+        self.refresh_entries(context);
+        self.refresh_preview(context);
+    }
+
+    fn clear(&mut self, context: &mut UIContext) {
+        for note in PAD_BAR_NOTES.iter().chain(PAD_PREVIEW_NOTES.iter()) {
+            context.pad.play_note(1, *note, 0, 0.0);
+        }
+        for cc in [PAD_SESSION_CC, PAD_UP_CC, PAD_DOWN_CC] {
+            context.pad.send_cc(1, cc, 0);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use crate::padseq::midi::MidiMessageType;
+use crate::padseq::session::DrumGrid;
+use crate::padseq::ui::{Screen, ScreenEvent, UIContext};
+
+const PAD_MUTE_NOTES: [u8; 8] = [81, 82, 83, 84, 85, 86, 87, 88];
+const PAD_SOLO_NOTES: [u8; 8] = [71, 72, 73, 74, 75, 76, 77, 78];
+const PAD_DRUM_NOTES: [u8; 8] = [61, 62, 63, 64, 65, 66, 67, 68];
+const PAD_ADD_BUTTON_NOTE: u8 = 11;
+const PAD_REMOVE_BUTTON_NOTE: u8 = 12;
+const PAD_LEFT_CC: u8 = 93;
+const PAD_RIGHT_CC: u8 = 94;
+const PAD_SESSION_CC: u8 = 95;
+const PAD_COLOR_ARROW: u8 = 41;
+const PAD_COLOR_UNMUTED: u8 = 21;
+const PAD_COLOR_MUTED: u8 = 5;
+const PAD_COLOR_UNSOLOED: u8 = 1;
+const PAD_COLOR_SOLOED: u8 = 45;
+const PAD_COLOR_KEYS: u8 = 1;
+const PAD_COLOR_DRUMS: u8 = 9;
+const PAD_COLOR_DRUM_GRID: u8 = 13;
+
+/// Mute and solo pads for every instrument, eight instruments per page. The
+/// row below switches the pattern screen of the instrument from a keyboard to
+/// two rows of drum pads, to a four by four grid of them and back. Instruments
+/// are added and the last one removed with the buttons below. The remove
+/// button flashes after the first press and only removes the instrument when
+/// it is pressed again before any other pad.
+pub struct Mixer {
+    page: usize,
+    // the remove button was pressed once, any other press cancels it
+    remove_armed: bool,
+}
+
+impl Mixer {
+    pub fn new() -> Mixer {
+        Mixer {
+            page: 0,
+            remove_armed: false,
+        }
+    }
+
+    fn number_of_pages(&self, context: &mut UIContext) -> usize {
+        let count = context.sequencer.get_session().number_of_instruments();
+        return count.div_ceil(PAD_MUTE_NOTES.len());
+    }
+
+    /// The instrument of the given column, if there is one.
+    fn get_instrument(&self, column: usize, context: &mut UIContext) -> Option<usize> {
+        let instrument = self.page * PAD_MUTE_NOTES.len() + column;
+        if instrument < context.sequencer.get_session().number_of_instruments() {
+            return Some(instrument);
+        }
+        return None;
+    }
+
+    fn refresh_instrument(&mut self, column: usize, context: &mut UIContext) {
+        let instrument = match self.get_instrument(column, context) {
+            Some(instrument) => instrument,
+            None => {
+                context.pad.play_note(1, PAD_MUTE_NOTES[column], 0, 0.0);
+                context.pad.play_note(1, PAD_SOLO_NOTES[column], 0, 0.0);
+                context.pad.play_note(1, PAD_DRUM_NOTES[column], 0, 0.0);
+                return;
+            }
+        };
+        let session = context.sequencer.get_session();
+        let muted = session.get_instrument(instrument).is_muted();
+        let soloed = session.get_instrument(instrument).is_soloed();
+        let drum = session.get_instrument(instrument).is_drum();
+        let grid = session.get_instrument(instrument).get_drum_grid();
+        // pulse instruments which are silenced by the solo of another one
+        let channel = if !muted && !session.is_audible(instrument) {
+            3
+        } else {
+            1
+        };
+        context.pad.play_note(
+            channel,
+            PAD_MUTE_NOTES[column],
+            if muted {
+                PAD_COLOR_MUTED
+            } else {
+                PAD_COLOR_UNMUTED
+            },
+            0.0,
+        );
+        context.pad.play_note(
+            1,
+            PAD_SOLO_NOTES[column],
+            if soloed {
+                PAD_COLOR_SOLOED
+            } else {
+                PAD_COLOR_UNSOLOED
+            },
+            0.0,
+        );
+        context.pad.play_note(
+            1,
+            PAD_DRUM_NOTES[column],
+            match (drum, grid) {
+                (false, _) => PAD_COLOR_KEYS,
+                (true, DrumGrid::EightByTwo) => PAD_COLOR_DRUMS,
+                (true, DrumGrid::FourByFour) => PAD_COLOR_DRUM_GRID,
+            },
+            0.0,
+        );
+    }
+}
+
+impl Screen for Mixer {
+    fn handle_pad_events(&mut self, context: &mut UIContext) -> ScreenEvent {
+        while context.pad.has_events() {
+            let event = context.pad.pop_event().unwrap();
+            let message = event.message;
+            if message.velocity == 0 {
+                continue;
+            }
+            let remove_armed = std::mem::replace(&mut self.remove_armed, false);
+            match message.r#type {
+                MidiMessageType::ControlChange => match message.note {
+                    PAD_SESSION_CC => {
+                        return ScreenEvent::SwitchToSession;
+                    }
+                    PAD_LEFT_CC => {
+                        self.page = self.page.saturating_sub(1);
+                    }
+                    PAD_RIGHT_CC if self.page + 1 < self.number_of_pages(context) => {
+                        self.page += 1;
+                    }
+                    _ => {}
+                },
+                MidiMessageType::NoteOn => {
+                    let note = message.note;
+                    if note == PAD_ADD_BUTTON_NOTE {
+                        context.sequencer.add_instrument();
+                    } else if note == PAD_REMOVE_BUTTON_NOTE {
+                        if remove_armed {
+                            context.sequencer.remove_instrument();
+                        } else {
+                            self.remove_armed = true;
+                        }
+                    } else if let Some(instrument) = PAD_MUTE_NOTES
+                        .iter()
+                        .position(|&x| x == note)
+                        .and_then(|x| self.get_instrument(x, context))
+                    {
+                        let session = context.sequencer.get_session_mut();
+                        let muted = session.get_instrument(instrument).is_muted();
+                        session.get_instrument_mut(instrument).set_muted(!muted);
+                        context.sequencer.save_session();
+                    } else if let Some(instrument) = PAD_SOLO_NOTES
+                        .iter()
+                        .position(|&x| x == note)
+                        .and_then(|x| self.get_instrument(x, context))
+                    {
+                        let session = context.sequencer.get_session_mut();
+                        let soloed = session.get_instrument(instrument).is_soloed();
+                        session.get_instrument_mut(instrument).set_soloed(!soloed);
+                        context.sequencer.save_session();
+                    } else if let Some(instrument) = PAD_DRUM_NOTES
+                        .iter()
+                        .position(|&x| x == note)
+                        .and_then(|x| self.get_instrument(x, context))
+                    {
+                        let instrument = context
+                            .sequencer
+                            .get_session_mut()
+                            .get_instrument_mut(instrument);
+                        let (drum, grid) = match (instrument.is_drum(), instrument.get_drum_grid())
+                        {
+                            (false, _) => (true, DrumGrid::EightByTwo),
+                            (true, DrumGrid::EightByTwo) => (true, DrumGrid::FourByFour),
+                            (true, DrumGrid::FourByFour) => (false, DrumGrid::EightByTwo),
+                        };
+                        instrument.set_drum(drum);
+                        instrument.set_drum_grid(grid);
+                        context.sequencer.save_session();
+                    }
+                }
+                _ => {}
+            }
+        }
+        context.pad.send_events();
+        return ScreenEvent::None;
+    }
+
+    fn refresh(&mut self, context: &mut UIContext) {
+        context.pad.send_cc(1, PAD_SESSION_CC, 41);
+        // the last page may have been removed
+        let pages = self.number_of_pages(context);
+        self.page = self.page.min(pages - 1);
+        for column in 0..PAD_MUTE_NOTES.len() {
+            self.refresh_instrument(column, context);
+        }
+        let left_color = if self.page > 0 { PAD_COLOR_ARROW } else { 0 };
+        context.pad.send_cc(1, PAD_LEFT_CC, left_color);
+        let right_color = if self.page + 1 < pages {
+            PAD_COLOR_ARROW
+        } else {
+            0
+        };
+        context.pad.send_cc(1, PAD_RIGHT_CC, right_color);
+        context.pad.play_note(1, PAD_ADD_BUTTON_NOTE, 21, 0.0);
+        let remove_channel = if self.remove_armed { 2 } else { 1 };
+        context
+            .pad
+            .play_note(remove_channel, PAD_REMOVE_BUTTON_NOTE, 5, 0.0);
+    }
+
+    fn clear(&mut self, context: &mut UIContext) {
+        for note in PAD_MUTE_NOTES
+            .iter()
+            .chain(PAD_SOLO_NOTES.iter())
+            .chain(PAD_DRUM_NOTES.iter())
+        {
+            context.pad.play_note(1, *note, 0, 0.0);
+        }
+        context.pad.play_note(1, PAD_ADD_BUTTON_NOTE, 0, 0.0);
+        context.pad.play_note(1, PAD_REMOVE_BUTTON_NOTE, 0, 0.0);
+        for cc in [PAD_SESSION_CC, PAD_LEFT_CC, PAD_RIGHT_CC] {
+            context.pad.send_cc(1, cc, 0);
+        }
+    }
+}
@@ -1,17 +1,65 @@
 use crate::padseq::midi::MidiMessageType;
-use crate::padseq::session::{Note, Step, StepNotes, BAR_SIZE};
+use crate::padseq::random::Random;
+use crate::padseq::scale::Scale;
+use crate::padseq::sequencer::RecordMode;
+use crate::padseq::session::{
+    Channel, DrumGrid, Note, Pattern as SessionPattern, Step, StepNotes, BAR_SIZE,
+};
 use crate::padseq::ui::{Screen, ScreenEvent, UIContext};
 use std::cmp;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 pub const PAD_BAR_NOTES: [Note; BAR_SIZE as usize] = [
     81, 82, 83, 84, 85, 86, 87, 88, 71, 72, 73, 74, 75, 76, 77, 78, 61, 62, 63, 64, 65, 66, 67, 68,
     51, 52, 53, 54, 55, 56, 57, 58,
 ];
 const PAD_KEY_NOTES: [Note; 13] = [21, 22, 32, 23, 33, 24, 25, 35, 26, 36, 27, 37, 28];
+/// The keys of the in-key layout, degrees of the scale from the lower row on.
+const PAD_IN_KEY_NOTES: [Note; 14] = [21, 22, 23, 24, 25, 26, 27, 28, 32, 33, 34, 35, 36, 37];
+/// The scales picked while the layout button is held, in the order of
+/// `Scale::PRESETS`.
+const PAD_SCALE_NOTES: [Note; 11] = [41, 42, 43, 44, 45, 46, 47, 48, 11, 12, 13];
+/// The drums of drum instruments in two rows, from the lower row on. Covers
+/// the pads of the key layouts as well.
+const PAD_DRUM_NOTES: [Note; 16] = [
+    21, 22, 23, 24, 25, 26, 27, 28, 31, 32, 33, 34, 35, 36, 37, 38,
+];
+/// The drums laid out four by four, from the lower row on.
+const PAD_DRUM_GRID_NOTES: [Note; 16] = [
+    11, 12, 13, 14, 21, 22, 23, 24, 31, 32, 33, 34, 41, 42, 43, 44,
+];
+/// Move the note of the selected drum by a semitone or an octave.
+const PAD_DRUM_NOTE_CCS: [(u8, i32); 4] = [(91, 1), (92, -1), (93, -12), (94, 12)];
 const PAD_PREV_OCTAVE: u8 = 31;
 const PAD_NEXT_OCTAVE: u8 = 38;
 const PAD_SESSION_CC: u8 = 95;
+const PAD_LAYOUT_CC: u8 = 96;
+const PAD_RECORD_CC: u8 = 19;
+const PAD_RECORD_MODE_CC: u8 = 29;
+/// Held while pressing steps, or pressed alone for the whole pattern.
+const PAD_COPY_CC: u8 = 89;
+const PAD_PASTE_CC: u8 = 79;
+const PAD_CLEAR_CC: u8 = 69;
+/// While held, the key pads transform the pattern.
+const PAD_TRANSFORM_CC: u8 = 59;
+const PAD_TRANSFORM_NOTES: [(Note, Transform); 10] = [
+    (21, Transform::RotateLeft),
+    (22, Transform::RotateRight),
+    (23, Transform::Reverse),
+    (24, Transform::HalfSpeed),
+    (25, Transform::DoubleSpeed),
+    (26, Transform::Humanize),
+    (32, Transform::Transpose(-1)),
+    (33, Transform::Transpose(1)),
+    (35, Transform::Transpose(-12)),
+    (36, Transform::Transpose(12)),
+];
+const PAD_EUCLID_CC: u8 = 49;
+const PAD_RANDOM_CC: u8 = 39;
+/// Largest change of a velocity when humanizing.
+const HUMANIZE_AMOUNT: u8 = 16;
+/// Milliseconds a drum plays when its note is moved.
+const DRUM_PREVIEW_LENGTH: f64 = 250.0;
 // const PAD_NEXT_CC: u8 = 94;
 // const PAD_PREV_CC: u8 = 93;
 const PAD_COLOR_STEP_OFF: u8 = 112;
@@ -21,25 +69,488 @@ const PAD_COLOR_STEP_SET_AND_ACTIVE: u8 = 78;
 const PAD_COLOR_STEP_ACTIVE: u8 = 3;
 const PAD_COLOR_KEY: u8 = 12;
 const PAD_COLOR_KEY_ACTIVE: u8 = 9;
+const PAD_COLOR_KEY_ROOT: u8 = 13;
+const PAD_COLOR_DRUM: u8 = 12;
+const PAD_COLOR_DRUM_SELECTED: u8 = 13;
+const PAD_COLOR_DRUM_NOTE: u8 = 41;
+const PAD_COLOR_LAYOUT: u8 = 1;
+const PAD_COLOR_LAYOUT_IN_KEY: u8 = 13;
+const PAD_COLOR_SCALE: u8 = 1;
+const PAD_COLOR_SCALE_ACTIVE: u8 = 45;
+const PAD_COLOR_RECORD: u8 = 5;
+const PAD_COLOR_OVERDUB: u8 = 13;
+const PAD_COLOR_REPLACE: u8 = 9;
+const PAD_COLOR_COPY: u8 = 124;
+const PAD_COLOR_PASTE: u8 = 21;
+const PAD_COLOR_PASTE_EMPTY: u8 = 1;
+const PAD_COLOR_CLEAR: u8 = 5;
+const PAD_COLOR_TRANSFORM: u8 = 49;
+const PAD_COLOR_EUCLID: u8 = 45;
+const PAD_COLOR_RANDOM: u8 = 53;
+const PAD_COLOR_TRANSFORM_TIME: u8 = 45;
+const PAD_COLOR_TRANSFORM_PITCH: u8 = 9;
 const MIN_OCTAVE: u8 = 1;
 const MAX_OCTAVE: u8 = 8;
 
 type SelectedNotes = HashSet<Note>;
 
+#[derive(Clone, Copy)]
+enum Transform {
+    RotateLeft,
+    RotateRight,
+    Reverse,
+    HalfSpeed,
+    DoubleSpeed,
+    Humanize,
+    Transpose(i32),
+}
+
+/// Copied steps, empty ones included, and the step they were copied from.
+struct Clipboard {
+    steps: Vec<Option<StepNotes>>,
+    origin: Step,
+}
+
+/// The copy, paste or clear button being held.
+struct HeldButton {
+    cc: u8,
+    // first step of a range, the second step pressed ends it
+    range_start: Option<Step>,
+    used: bool,
+}
+
+/// Edits the steps of a pattern.
+///
+/// Holding copy or clear and pressing a step copies or clears it, pressing a
+/// second step extends that to the range between both. Holding paste and
+/// pressing a step pastes the copied steps from there up to the end of the
+/// bar. Pressing a button alone copies, pastes or clears the whole pattern,
+/// so copying it and pasting at the middle duplicates the first half.
+///
+/// While the transform button is held the lower key row rotates, reverses,
+/// slows down, speeds up or humanizes the pattern, and the upper one
+/// transposes it by a semitone or an octave.
+///
+/// Tapping the layout button switches the keys between a chromatic keyboard
+/// and one playing only the notes of the session key, roots highlighted.
+/// While it is held the keys pick the root and the pads above them the scale.
+///
+/// Drum instruments show their drums instead of keys, in two rows or a four by
+/// four grid as set on the mixer. Pressing a drum selects it, the steps then
+/// only show and edit its hits until it is pressed again. The arrow buttons
+/// move the note of the selected drum by a semitone or an octave. The layout
+/// button does nothing for drums.
 pub struct Pattern {
     instrument: usize,
     pattern: usize,
     selected_notes: SelectedNotes,
+    // the notes of the held key pads, stopped on release even if the layout,
+    // octave or key changed in between
+    held_keys: HashMap<Note, Note>,
     octave: u8,
+    clipboard: Option<Clipboard>,
+    held_button: Option<HeldButton>,
+    transform_held: bool,
+    in_key: bool,
+    layout_held: bool,
+    // a root or scale was picked while the layout button was held
+    layout_used: bool,
+    selected_drum: Option<usize>,
+    random: Random,
 }
 
 impl Pattern {
     pub fn new(instrument: usize, pattern: usize) -> Pattern {
         Pattern {
             selected_notes: SelectedNotes::new(),
+            held_keys: HashMap::new(),
             pattern: pattern,
             instrument: instrument,
             octave: 5,
+            clipboard: None,
+            held_button: None,
+            transform_held: false,
+            in_key: false,
+            layout_held: false,
+            layout_used: false,
+            selected_drum: None,
+            random: Random::from_time(),
+        }
+    }
+
+    fn transform(&mut self, transform: Transform, context: &mut UIContext) {
+        let mut the_pattern = self.get_pattern(context);
+        match transform {
+            Transform::RotateLeft => the_pattern.rotate(-1),
+            Transform::RotateRight => the_pattern.rotate(1),
+            Transform::Reverse => the_pattern.reverse(),
+            Transform::HalfSpeed => the_pattern.half_speed(),
+            Transform::DoubleSpeed => the_pattern.double_speed(),
+            Transform::Humanize => the_pattern.humanize(HUMANIZE_AMOUNT, &mut self.random),
+            Transform::Transpose(semitones) => the_pattern.transpose(semitones),
+        }
+        context
+            .sequencer
+            .set_pattern(self.instrument, self.pattern, &the_pattern);
+    }
+
+    /// The root of the session key in the current octave.
+    fn get_root_note(&self, context: &mut UIContext) -> Note {
+        return self.octave * 12 + context.sequencer.get_session().get_root();
+    }
+
+    fn is_drum(&self, context: &mut UIContext) -> bool {
+        return context
+            .sequencer
+            .get_session()
+            .get_instrument(self.instrument)
+            .is_drum();
+    }
+
+    /// The note of the selected drum, `None` if there is none or the
+    /// instrument plays keys.
+    fn get_selected_drum(&self, context: &mut UIContext) -> Option<Note> {
+        if !self.is_drum(context) {
+            return None;
+        }
+        let instrument = context
+            .sequencer
+            .get_session()
+            .get_instrument(self.instrument);
+        return self
+            .selected_drum
+            .and_then(|x| instrument.get_drums().get(x))
+            .map(|x| x.get_note());
+    }
+
+    fn select_drum(&mut self, index: usize, context: &mut UIContext) {
+        self.selected_drum = if self.selected_drum == Some(index) {
+            None
+        } else {
+            Some(index)
+        };
+        if let Some(drum) = self.selected_drum.and_then(|x| {
+            context
+                .sequencer
+                .get_session()
+                .get_instrument(self.instrument)
+                .get_drums()
+                .get(x)
+        }) {
+            println!(
+                "{} ({}) is the selected drum",
+                drum.get_name(),
+                drum.get_note()
+            );
+        }
+    }
+
+    /// The MIDI channel the instrument plays on.
+    fn get_channel(&self, context: &mut UIContext) -> Channel {
+        return context
+            .sequencer
+            .get_session()
+            .get_instrument(self.instrument)
+            .get_channel();
+    }
+
+    /// Moves the note of the selected drum and plays it to listen.
+    fn move_drum_note(&mut self, offset: i32, context: &mut UIContext) {
+        let (index, note) = match (self.selected_drum, self.get_selected_drum(context)) {
+            (Some(index), Some(note)) => (index, note),
+            _ => return,
+        };
+        let note = (note as i32 + offset).clamp(0, 127) as Note;
+        let instrument = context
+            .sequencer
+            .get_session_mut()
+            .get_instrument_mut(self.instrument);
+        instrument.set_drum_note(index, note);
+        context.sequencer.save_session();
+        let channel = self.get_channel(context);
+        context.sequencer.get_instrument(self.instrument).play_note(
+            channel,
+            note,
+            127,
+            DRUM_PREVIEW_LENGTH,
+        );
+    }
+
+    /// The notes the steps are edited with: the held keys, or the selected
+    /// drum if none are.
+    fn get_selected_notes(&self, context: &mut UIContext) -> Vec<Note> {
+        let mut notes: Vec<Note> = self.selected_notes.iter().copied().collect();
+        notes.sort();
+        if notes.is_empty() {
+            notes.extend(self.get_selected_drum(context));
+        }
+        return notes;
+    }
+
+    /// Whether the step lights as set, only the hits of the selected drum
+    /// count if there is one.
+    fn shows_step(&self, step: Step, context: &mut UIContext) -> bool {
+        let drum = self.get_selected_drum(context);
+        let the_pattern = context
+            .sequencer
+            .get_session()
+            .get_instrument(self.instrument)
+            .get_pattern(self.pattern)
+            .unwrap();
+        if !the_pattern.has_step_set(step) {
+            return false;
+        }
+        return match drum {
+            Some(drum) => the_pattern.get_step(step).contains_key(&drum),
+            None => true,
+        };
+    }
+
+    /// The key pads of the current layout, in the order of their notes.
+    fn get_key_pads(&self, context: &mut UIContext) -> &'static [Note] {
+        if self.is_drum(context) {
+            match context
+                .sequencer
+                .get_session()
+                .get_instrument(self.instrument)
+                .get_drum_grid()
+            {
+                DrumGrid::EightByTwo => &PAD_DRUM_NOTES,
+                DrumGrid::FourByFour => &PAD_DRUM_GRID_NOTES,
+            }
+        } else if self.in_key {
+            &PAD_IN_KEY_NOTES
+        } else {
+            &PAD_KEY_NOTES
+        }
+    }
+
+    /// The note played by the key pad at the index, `None` beyond the MIDI
+    /// range.
+    fn get_key_note(&self, index: usize, context: &mut UIContext) -> Option<Note> {
+        if self.is_drum(context) {
+            let instrument = context
+                .sequencer
+                .get_session()
+                .get_instrument(self.instrument);
+            return instrument.get_drums().get(index).map(|x| x.get_note());
+        }
+        if !self.in_key {
+            return Some(self.octave * 12 - 1 + index as Note);
+        }
+        let root = self.get_root_note(context);
+        return context
+            .sequencer
+            .get_session()
+            .get_scale()
+            .get_note(root, index);
+    }
+
+    fn get_key_color(&self, index: usize, context: &mut UIContext) -> u8 {
+        if self.is_drum(context) {
+            return if self.get_key_note(index, context).is_none() {
+                0
+            } else if self.selected_drum == Some(index) {
+                PAD_COLOR_DRUM_SELECTED
+            } else {
+                PAD_COLOR_DRUM
+            };
+        }
+        if !self.in_key {
+            // marks the middle C
+            return if index == 1 && self.octave == 5 {
+                PAD_COLOR_KEY_ROOT
+            } else {
+                PAD_COLOR_KEY
+            };
+        }
+        if self.get_key_note(index, context).is_none() {
+            return 0;
+        }
+        if context.sequencer.get_session().get_scale().is_root(index) {
+            PAD_COLOR_KEY_ROOT
+        } else {
+            PAD_COLOR_KEY
+        }
+    }
+
+    fn clear_key_pads(&mut self, context: &mut UIContext) {
+        for note in PAD_DRUM_NOTES.iter().chain(PAD_DRUM_GRID_NOTES.iter()) {
+            context.pad.play_note(1, *note, 0, 0.0);
+        }
+    }
+
+    fn refresh_layout_pads(&mut self, context: &mut UIContext) {
+        let root = context.sequencer.get_session().get_root();
+        let scale = context.sequencer.get_session().get_scale().clone();
+        for (index, note) in PAD_KEY_NOTES.iter().enumerate() {
+            // the keys start at the B below the C
+            let color = if (index + 11) % 12 == root as usize {
+                PAD_COLOR_KEY_ROOT
+            } else {
+                PAD_COLOR_KEY
+            };
+            context.pad.play_note(1, *note, color, 0.0);
+        }
+        for (preset, note) in Scale::PRESETS.iter().zip(PAD_SCALE_NOTES) {
+            let color = if *preset == scale {
+                PAD_COLOR_SCALE_ACTIVE
+            } else {
+                PAD_COLOR_SCALE
+            };
+            context.pad.play_note(1, note, color, 0.0);
+        }
+    }
+
+    fn press_layout_button(&mut self, pressed: bool, context: &mut UIContext) {
+        self.clear_key_pads(context);
+        if pressed {
+            self.layout_held = true;
+            self.layout_used = false;
+            self.refresh_layout_pads(context);
+            return;
+        }
+        self.layout_held = false;
+        if !self.layout_used {
+            self.in_key = !self.in_key;
+        }
+        for note in PAD_SCALE_NOTES {
+            context.pad.play_note(1, note, 0, 0.0);
+        }
+        self.prepare_step(context);
+    }
+
+    fn pick_key(&mut self, note: Note, context: &mut UIContext) {
+        let session = context.sequencer.get_session_mut();
+        if let Some(index) = PAD_KEY_NOTES.iter().position(|&x| x == note) {
+            session.set_root(((index + 11) % 12) as Note);
+        } else if let Some(index) = PAD_SCALE_NOTES.iter().position(|&x| x == note) {
+            session.set_scale(Scale::PRESETS[index].clone());
+        }
+        context.sequencer.save_session();
+        self.layout_used = true;
+        self.refresh_layout_pads(context);
+    }
+
+    /// The selected notes, or the root of the octave if there are none.
+    fn get_generator_notes(&self, context: &mut UIContext) -> Vec<Note> {
+        let notes = self.get_selected_notes(context);
+        if notes.is_empty() {
+            return vec![self.get_root_note(context)];
+        }
+        return notes;
+    }
+
+    fn refresh_transform_pads(&mut self, context: &mut UIContext) {
+        self.clear_key_pads(context);
+        for (note, transform) in PAD_TRANSFORM_NOTES {
+            let color = match transform {
+                Transform::Transpose(_) => PAD_COLOR_TRANSFORM_PITCH,
+                _ => PAD_COLOR_TRANSFORM_TIME,
+            };
+            context.pad.play_note(1, note, color, 0.0);
+        }
+    }
+
+    fn get_pattern(&self, context: &mut UIContext) -> SessionPattern {
+        return context
+            .sequencer
+            .get_session()
+            .get_instrument(self.instrument)
+            .get_pattern(self.pattern)
+            .unwrap()
+            .clone();
+    }
+
+    fn copy(&mut self, first: Step, last: Step, context: &mut UIContext) {
+        let the_pattern = self.get_pattern(context);
+        let steps = (first..=last)
+            .map(|step| match the_pattern.has_step_set(step) {
+                true => Some(the_pattern.get_step(step).clone()),
+                false => None,
+            })
+            .collect();
+        self.clipboard = Some(Clipboard {
+            steps: steps,
+            origin: first,
+        });
+    }
+
+    fn paste(&mut self, start: Step, context: &mut UIContext) {
+        let clipboard = match &self.clipboard {
+            Some(clipboard) => clipboard,
+            None => return,
+        };
+        let mut the_pattern = self.get_pattern(context);
+        for (step, notes) in (start..BAR_SIZE).zip(clipboard.steps.iter()) {
+            match notes {
+                Some(notes) => the_pattern.set_step(step, notes),
+                None => the_pattern.clear_step(step),
+            }
+        }
+        context
+            .sequencer
+            .set_pattern(self.instrument, self.pattern, &the_pattern);
+    }
+
+    fn clear_steps(&mut self, first: Step, last: Step, context: &mut UIContext) {
+        let mut the_pattern = self.get_pattern(context);
+        for step in first..=last {
+            the_pattern.clear_step(step);
+        }
+        context
+            .sequencer
+            .set_pattern(self.instrument, self.pattern, &the_pattern);
+    }
+
+    fn press_edit_button(&mut self, cc: u8, pressed: bool, context: &mut UIContext) {
+        if pressed {
+            self.held_button = Some(HeldButton {
+                cc: cc,
+                range_start: None,
+                used: false,
+            });
+            return;
+        }
+        let held = match self.held_button.take() {
+            Some(held) if held.cc == cc => held,
+            other => {
+                self.held_button = other;
+                return;
+            }
+        };
+        if held.used {
+            return;
+        }
+        match cc {
+            PAD_COPY_CC => self.copy(0, BAR_SIZE - 1, context),
+            PAD_PASTE_CC => {
+                if let Some(origin) = self.clipboard.as_ref().map(|x| x.origin) {
+                    self.paste(origin, context);
+                }
+            }
+            _ => self.clear_steps(0, BAR_SIZE - 1, context),
+        }
+    }
+
+    fn press_step_while_held(&mut self, step: Step, context: &mut UIContext) {
+        let held = self.held_button.as_mut().unwrap();
+        held.used = true;
+        let cc = held.cc;
+        let range = match held.range_start {
+            Some(start) => {
+                held.range_start = None;
+                (cmp::min(start, step), cmp::max(start, step))
+            }
+            None => {
+                held.range_start = Some(step);
+                (step, step)
+            }
+        };
+        match cc {
+            PAD_COPY_CC => self.copy(range.0, range.1, context),
+            PAD_PASTE_CC => self.paste(step, context),
+            _ => self.clear_steps(range.0, range.1, context),
         }
     }
 
@@ -51,14 +562,7 @@ impl Pattern {
             1
         };
         if step == context.sequencer.get_active_step() {
-            if context
-                .sequencer
-                .get_session()
-                .get_instrument(self.instrument)
-                .get_pattern(self.pattern)
-                .unwrap()
-                .has_step_set(step)
-            {
+            if self.shows_step(step, context) {
                 context
                     .pad
                     .play_note(channel, note, PAD_COLOR_STEP_SET_AND_ACTIVE, 0.0);
@@ -68,16 +572,10 @@ impl Pattern {
                     .play_note(channel, note, PAD_COLOR_STEP_ACTIVE, 0.0);
             }
         } else {
-            if context
-                .sequencer
-                .get_session()
-                .get_instrument(self.instrument)
-                .get_pattern(self.pattern)
-                .unwrap()
-                .has_step_set(step)
-            {
-                let mut any_missing = self.selected_notes.len() == 0;
-                for note in self.selected_notes.clone() {
+            if self.shows_step(step, context) {
+                let selected_notes = self.get_selected_notes(context);
+                let mut any_missing = selected_notes.is_empty();
+                for note in selected_notes {
                     if !context
                         .sequencer
                         .get_session()
@@ -127,22 +625,30 @@ impl Pattern {
 
 impl Screen for Pattern {
     fn prepare_step(&mut self, context: &mut UIContext) {
+        if self.transform_held {
+            self.refresh_transform_pads(context);
+            return;
+        }
+        if self.layout_held {
+            self.refresh_layout_pads(context);
+            return;
+        }
         // clear highlighted notes
-        for note in PAD_KEY_NOTES {
-            let color = if note == PAD_KEY_NOTES[1] && self.octave == 5 {
-                13
-            } else {
-                PAD_COLOR_KEY
-            };
-            context.pad.play_note(1, note, color, 0.0);
+        for (index, note) in self.get_key_pads(context).iter().enumerate() {
+            let color = self.get_key_color(index, context);
+            context.pad.play_note(1, *note, color, 0.0);
+        }
+        // the octave buttons are drums as well
+        if self.is_drum(context) {
+            return;
         }
 
-        if (self.octave < MAX_OCTAVE) {
+        if self.octave < MAX_OCTAVE {
             context.pad.send_cc(1, PAD_NEXT_OCTAVE, 55);
         } else {
             context.pad.send_cc(1, PAD_NEXT_OCTAVE, 0);
         }
-        if (self.octave > MIN_OCTAVE) {
+        if self.octave > MIN_OCTAVE {
             context.pad.send_cc(1, PAD_PREV_OCTAVE, 55);
         } else {
             context.pad.send_cc(1, PAD_PREV_OCTAVE, 0);
@@ -156,11 +662,62 @@ impl Screen for Pattern {
             let note = message.note;
             match message.r#type {
                 MidiMessageType::ControlChange => {
+                    if message.note == PAD_TRANSFORM_CC {
+                        self.transform_held = message.velocity > 0;
+                        if self.transform_held {
+                            self.refresh_transform_pads(context);
+                        }
+                        continue;
+                    }
+                    if message.note == PAD_LAYOUT_CC {
+                        if !self.is_drum(context) {
+                            self.press_layout_button(message.velocity > 0, context);
+                        }
+                        continue;
+                    }
+                    if let Some((_, offset)) =
+                        PAD_DRUM_NOTE_CCS.iter().find(|x| x.0 == message.note)
+                    {
+                        if message.velocity > 0 {
+                            self.move_drum_note(*offset, context);
+                        }
+                        continue;
+                    }
+                    if [PAD_COPY_CC, PAD_PASTE_CC, PAD_CLEAR_CC].contains(&message.note) {
+                        self.press_edit_button(message.note, message.velocity > 0, context);
+                        continue;
+                    }
                     if message.velocity > 0 {
                         match message.note {
                             PAD_SESSION_CC => {
                                 return ScreenEvent::SwitchToSession;
                             }
+                            PAD_EUCLID_CC => {
+                                return ScreenEvent::SwitchToEuclid(
+                                    self.instrument,
+                                    self.pattern,
+                                    self.get_generator_notes(context),
+                                );
+                            }
+                            PAD_RANDOM_CC => {
+                                return ScreenEvent::SwitchToRandom(
+                                    self.instrument,
+                                    self.pattern,
+                                    self.get_selected_notes(context),
+                                    self.get_root_note(context),
+                                );
+                            }
+                            PAD_RECORD_CC => {
+                                let recording = context.sequencer.is_recording();
+                                context.sequencer.set_recording(!recording);
+                            }
+                            PAD_RECORD_MODE_CC => {
+                                let mode = match context.sequencer.get_record_mode() {
+                                    RecordMode::Overdub => RecordMode::Replace,
+                                    RecordMode::Replace => RecordMode::Overdub,
+                                };
+                                context.sequencer.set_record_mode(mode);
+                            }
                             // PAD_NEXT_CC => {
                             //     self.instrument =
                             //         cmp::min(NUMBER_OF_INSTRUMENTS, self.instrument + 1);
@@ -174,51 +731,75 @@ impl Screen for Pattern {
                     println!("{} is new active instrument", self.instrument);
                 }
                 _ => {
-                    if PAD_KEY_NOTES.contains(&note) {
-                        let key_note = self.octave * 12 - 1
-                            + PAD_KEY_NOTES.iter().position(|&x| x == note).unwrap() as Note;
-                        match message.r#type {
-                            MidiMessageType::NoteOn => {
-                                context.sequencer.get_instrument(self.instrument).play_note(
-                                    1,
-                                    key_note,
-                                    message.velocity,
-                                    0.0,
-                                );
-                                context.pad.play_note(1, note, PAD_COLOR_KEY_ACTIVE, 0.0);
-                                match message.velocity {
-                                    0 => {
-                                        self.selected_notes.remove(&key_note);
-                                    }
-                                    _ => {
-                                        self.selected_notes.insert(key_note);
-                                    }
-                                }
-                            }
-                            MidiMessageType::NoteOff => {
-                                context
-                                    .sequencer
-                                    .get_instrument(self.instrument)
-                                    .stop_note(1, key_note);
-                                context.pad.play_note(1, note, PAD_COLOR_KEY, 0.0);
-                                self.selected_notes.remove(&key_note);
-                            }
-                            _ => {
-                                panic!()
-                            }
+                    let pressed = message.r#type == MidiMessageType::NoteOn && message.velocity > 0;
+                    // releases still reach the keys so held notes stop
+                    if self.layout_held
+                        && pressed
+                        && (PAD_KEY_NOTES.contains(&note) || PAD_SCALE_NOTES.contains(&note))
+                    {
+                        self.pick_key(note, context);
+                    } else if self.transform_held
+                        && PAD_IN_KEY_NOTES.contains(&note)
+                        && message.r#type == MidiMessageType::NoteOn
+                        && message.velocity > 0
+                    {
+                        if let Some((_, transform)) =
+                            PAD_TRANSFORM_NOTES.iter().find(|x| x.0 == note)
+                        {
+                            self.transform(*transform, context);
+                        }
+                    } else if !pressed && self.held_keys.contains_key(&note) {
+                        let key_note = self.held_keys.remove(&note).unwrap();
+                        let channel = self.get_channel(context);
+                        context
+                            .sequencer
+                            .get_instrument(self.instrument)
+                            .stop_note(channel, key_note);
+                        self.selected_notes.remove(&key_note);
+                        // the pad may belong to another layout by now
+                        let color = match self.get_key_pads(context).iter().position(|&x| x == note)
+                        {
+                            Some(index) => self.get_key_color(index, context),
+                            None => 0,
+                        };
+                        context.pad.play_note(1, note, color, 0.0);
+                    } else if let Some(index) =
+                        self.get_key_pads(context).iter().position(|&x| x == note)
+                    {
+                        if !pressed {
+                            continue;
+                        }
+                        let key_note = match self.get_key_note(index, context) {
+                            Some(key_note) => key_note,
+                            None => continue,
+                        };
+                        let channel = self.get_channel(context);
+                        context.sequencer.get_instrument(self.instrument).play_note(
+                            channel,
+                            key_note,
+                            message.velocity,
+                            0.0,
+                        );
+                        context.pad.play_note(1, note, PAD_COLOR_KEY_ACTIVE, 0.0);
+                        self.held_keys.insert(note, key_note);
+                        self.selected_notes.insert(key_note);
+                        if self.is_drum(context) {
+                            self.select_drum(index, context);
                         }
                     } else if PAD_BAR_NOTES.contains(&note) {
                         if message.velocity > 0 {
                             let step =
                                 PAD_BAR_NOTES.iter().position(|&x| x == note).unwrap() as Step;
-                            if self.selected_notes.len() == 0 {
-                                context
-                                    .sequencer
-                                    .get_session_mut()
-                                    .get_instrument_mut(self.instrument)
-                                    .get_pattern_mut(self.pattern)
-                                    .unwrap()
-                                    .clear_step(step);
+                            let selected_notes = self.get_selected_notes(context);
+                            if self.held_button.is_some() {
+                                self.press_step_while_held(step, context);
+                            } else if selected_notes.is_empty() {
+                                context.sequencer.set_step(
+                                    self.instrument,
+                                    self.pattern,
+                                    step,
+                                    None,
+                                );
                             } else {
                                 let mut step_notes = if context
                                     .sequencer
@@ -239,33 +820,31 @@ impl Screen for Pattern {
                                 } else {
                                     StepNotes::new()
                                 };
-                                for note in &self.selected_notes {
+                                for note in &selected_notes {
                                     if step_notes.contains_key(note) {
                                         step_notes.remove(note);
                                     } else {
                                         step_notes.insert(*note, 127);
                                     }
                                 }
-                                context
-                                    .sequencer
-                                    .get_session_mut()
-                                    .get_instrument_mut(self.instrument)
-                                    .get_pattern_mut(self.pattern)
-                                    .unwrap()
-                                    .set_step(step, &step_notes);
+                                context.sequencer.set_step(
+                                    self.instrument,
+                                    self.pattern,
+                                    step,
+                                    Some(step_notes),
+                                );
                             }
-                            context.sequencer.save_session();
                         }
                     } else if note == PAD_NEXT_OCTAVE
                         && self.octave < MAX_OCTAVE
                         && message.velocity > 0
                     {
-                        self.octave = self.octave + 1;
+                        self.octave += 1;
                     } else if note == PAD_PREV_OCTAVE
                         && self.octave > MIN_OCTAVE
                         && message.velocity > 0
                     {
-                        self.octave = self.octave - 1;
+                        self.octave -= 1;
                     }
                 }
             }
@@ -276,25 +855,75 @@ impl Screen for Pattern {
 
     fn refresh(&mut self, context: &mut UIContext) {
         context.pad.send_cc(1, PAD_SESSION_CC, 41);
+        let layout_color = if self.is_drum(context) {
+            0
+        } else if self.in_key {
+            PAD_COLOR_LAYOUT_IN_KEY
+        } else {
+            PAD_COLOR_LAYOUT
+        };
+        context.pad.send_cc(1, PAD_LAYOUT_CC, layout_color);
+        let drum_note_color = if self.get_selected_drum(context).is_some() {
+            PAD_COLOR_DRUM_NOTE
+        } else {
+            0
+        };
+        for (cc, _) in PAD_DRUM_NOTE_CCS {
+            context.pad.send_cc(1, cc, drum_note_color);
+        }
+        context.pad.send_cc(
+            if context.sequencer.is_recording() {
+                3
+            } else {
+                1
+            },
+            PAD_RECORD_CC,
+            PAD_COLOR_RECORD,
+        );
+        context.pad.send_cc(
+            1,
+            PAD_RECORD_MODE_CC,
+            match context.sequencer.get_record_mode() {
+                RecordMode::Overdub => PAD_COLOR_OVERDUB,
+                RecordMode::Replace => PAD_COLOR_REPLACE,
+            },
+        );
+        context.pad.send_cc(1, PAD_COPY_CC, PAD_COLOR_COPY);
+        context.pad.send_cc(
+            1,
+            PAD_PASTE_CC,
+            match self.clipboard {
+                Some(_) => PAD_COLOR_PASTE,
+                None => PAD_COLOR_PASTE_EMPTY,
+            },
+        );
+        context.pad.send_cc(1, PAD_CLEAR_CC, PAD_COLOR_CLEAR);
+        context
+            .pad
+            .send_cc(1, PAD_TRANSFORM_CC, PAD_COLOR_TRANSFORM);
+        context.pad.send_cc(1, PAD_EUCLID_CC, PAD_COLOR_EUCLID);
+        context.pad.send_cc(1, PAD_RANDOM_CC, PAD_COLOR_RANDOM);
         for n in 0..BAR_SIZE {
             self.refresh_step(n, context);
         }
     }
 
     fn on_played_note(&mut self, context: &mut UIContext, instrument: usize, note: Note) {
-        if instrument == self.instrument
-            && note >= (self.octave * 12 - 1)
-            && note < (self.octave + 1) * 12
-        {
-            let key_note = PAD_KEY_NOTES[(note - (self.octave * 12 - 1)) as usize];
-            context
-                .pad
-                .play_note(1, key_note, PAD_COLOR_KEY_ACTIVE, 0.0);
+        if instrument != self.instrument {
+            return;
+        }
+        for (index, key_note) in self.get_key_pads(context).iter().enumerate() {
+            if self.get_key_note(index, context) == Some(note) {
+                context
+                    .pad
+                    .play_note(1, *key_note, PAD_COLOR_KEY_ACTIVE, 0.0);
+            }
         }
     }
 
     fn clear(&mut self, context: &mut UIContext) {
-        for note in PAD_KEY_NOTES {
+        self.clear_key_pads(context);
+        for note in PAD_SCALE_NOTES {
             context.pad.play_note(1, note, 0, 0.0);
         }
         for note in PAD_BAR_NOTES {
@@ -303,5 +932,21 @@ impl Screen for Pattern {
         context.pad.send_cc(1, PAD_NEXT_OCTAVE, 0);
         context.pad.send_cc(1, PAD_PREV_OCTAVE, 0);
         context.pad.send_cc(1, PAD_SESSION_CC, 0);
+        context.pad.send_cc(1, PAD_LAYOUT_CC, 0);
+        for (cc, _) in PAD_DRUM_NOTE_CCS {
+            context.pad.send_cc(1, cc, 0);
+        }
+        context.pad.send_cc(1, PAD_RECORD_CC, 0);
+        context.pad.send_cc(1, PAD_RECORD_MODE_CC, 0);
+        for cc in [
+            PAD_COPY_CC,
+            PAD_PASTE_CC,
+            PAD_CLEAR_CC,
+            PAD_TRANSFORM_CC,
+            PAD_EUCLID_CC,
+            PAD_RANDOM_CC,
+        ] {
+            context.pad.send_cc(1, cc, 0);
+        }
     }
 }
@@ -0,0 +1,95 @@
+use crate::padseq::midi::MidiMessageType;
+use crate::padseq::ui::{Screen, ScreenEvent, UIContext};
+
+const PAD_SESSION_CC: u8 = 95;
+const PAD_COLUMNS: usize = 8;
+const PAD_ROWS: usize = 8;
+const PAD_COLOR_PROJECT: u8 = 45;
+const PAD_COLOR_PROJECT_CURRENT: u8 = 21;
+
+/// Every pad is a session file of the project directory, from the top left
+/// on. Pressing one loads it and cross-fades to it over the next bar after,
+/// it flashes until then.
+pub struct Projects {}
+
+impl Projects {
+    pub fn new() -> Projects {
+        Projects {}
+    }
+
+    fn get_note(slot: usize) -> u8 {
+        let row = PAD_ROWS - slot / PAD_COLUMNS;
+        let column = slot % PAD_COLUMNS + 1;
+        return (row * 10 + column) as u8;
+    }
+
+    fn get_slot(note: u8) -> Option<usize> {
+        let row = (note / 10) as usize;
+        let column = (note % 10) as usize;
+        if !(1..=PAD_ROWS).contains(&row) || !(1..=PAD_COLUMNS).contains(&column) {
+            return None;
+        }
+        return Some((PAD_ROWS - row) * PAD_COLUMNS + column - 1);
+    }
+
+    fn number_of_projects(context: &mut UIContext) -> usize {
+        return context.sequencer.get_projects().map_or(0, |x| x.len());
+    }
+}
+
+impl Screen for Projects {
+    fn handle_pad_events(&mut self, context: &mut UIContext) -> ScreenEvent {
+        while context.pad.has_events() {
+            let event = context.pad.pop_event().unwrap();
+            let message = event.message;
+            if message.velocity == 0 {
+                continue;
+            }
+            match message.r#type {
+                MidiMessageType::ControlChange if message.note == PAD_SESSION_CC => {
+                    return ScreenEvent::SwitchToSession;
+                }
+                MidiMessageType::NoteOn => {
+                    if let Some(slot) = Projects::get_slot(message.note) {
+                        if slot < Projects::number_of_projects(context) {
+                            context.sequencer.queue_project(slot);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        context.pad.send_events();
+        return ScreenEvent::None;
+    }
+
+    fn refresh(&mut self, context: &mut UIContext) {
+        context.pad.send_cc(1, PAD_SESSION_CC, 41);
+        let count = Projects::number_of_projects(context);
+        let current = context.sequencer.get_project();
+        let queued = context.sequencer.get_queued_project();
+        for slot in 0..PAD_ROWS * PAD_COLUMNS {
+            let note = Projects::get_note(slot);
+            if slot >= count {
+                context.pad.play_note(1, note, 0, 0.0);
+            } else if queued == Some(slot) {
+                context
+                    .pad
+                    .play_note(2, note, PAD_COLOR_PROJECT_CURRENT, 0.0);
+            } else if slot == current {
+                context
+                    .pad
+                    .play_note(1, note, PAD_COLOR_PROJECT_CURRENT, 0.0);
+            } else {
+                context.pad.play_note(1, note, PAD_COLOR_PROJECT, 0.0);
+            }
+        }
+    }
+
+    fn clear(&mut self, context: &mut UIContext) {
+        for slot in 0..PAD_ROWS * PAD_COLUMNS {
+            context.pad.play_note(1, Projects::get_note(slot), 0, 0.0);
+        }
+        context.pad.send_cc(1, PAD_SESSION_CC, 0);
+    }
+}
@@ -0,0 +1,260 @@
+use super::pattern::PAD_BAR_NOTES;
+
+use crate::padseq::generators::{random_pattern, Mutation, RandomSettings};
+use crate::padseq::midi::MidiMessageType;
+use crate::padseq::random::Random;
+use crate::padseq::scale::Scale;
+use crate::padseq::session::{Note, Velocity, BAR_SIZE};
+use crate::padseq::ui::{Screen, ScreenEvent, UIContext};
+
+/// Percentage of steps set, in eighths.
+const PAD_DENSITY_NOTES: [u8; 8] = [41, 42, 43, 44, 45, 46, 47, 48];
+/// The held keys, the session scale and then the scales of `POOL_SCALES`,
+/// all from the root on.
+const PAD_POOL_NOTES: [u8; 8] = [31, 32, 33, 34, 35, 36, 37, 38];
+const PAD_MIN_VELOCITY_NOTES: [(u8, Velocity); 4] = [(21, 1), (22, 32), (23, 64), (24, 96)];
+const PAD_MAX_VELOCITY_NOTES: [(u8, Velocity); 4] = [(25, 32), (26, 64), (27, 96), (28, 127)];
+/// Changes that many eighths of the steps.
+const PAD_MUTATE_NOTES: [u8; 8] = [11, 12, 13, 14, 15, 16, 17, 18];
+const POOL_SCALES: [Scale; 6] = [
+    Scale::Major,
+    Scale::Minor,
+    Scale::MajorPentatonic,
+    Scale::MinorPentatonic,
+    Scale::Blues,
+    Scale::Chromatic,
+];
+const PAD_COMMIT_CC: u8 = 89;
+const PAD_CANCEL_CC: u8 = 79;
+const PAD_NEW_SEED_CC: u8 = 69;
+const PAD_COLOR_STEP: u8 = 21;
+const PAD_COLOR_REST: u8 = 1;
+const PAD_COLOR_VALUE: u8 = 45;
+const PAD_COLOR_VALUE_UNSET: u8 = 1;
+const PAD_COLOR_MUTATE: u8 = 9;
+const PAD_COLOR_COMMIT: u8 = 21;
+const PAD_COLOR_CANCEL: u8 = 5;
+const PAD_COLOR_NEW_SEED: u8 = 49;
+
+fn get_density(index: usize) -> u8 {
+    return ((index + 1) * 100 / PAD_DENSITY_NOTES.len()) as u8;
+}
+
+/// Generates random patterns for ideas.
+///
+/// The step pads show the result. The rows below set the density, the notes
+/// to pick from, the lowest and highest velocity, and the lowest row changes
+/// that many eighths of the steps. The side buttons commit, cancel or start
+/// over with a new seed. The settings are stored with the pattern, reopening
+/// the generator continues from them.
+pub struct RandomGenerator {
+    instrument: usize,
+    pattern: usize,
+    held_keys: Vec<Note>,
+    root: Note,
+    scale: Scale,
+    settings: RandomSettings,
+    // the selected pad of the note pool, `None` for notes stored earlier
+    pool: Option<usize>,
+    random: Random,
+}
+
+impl RandomGenerator {
+    pub fn new(
+        instrument: usize,
+        pattern: usize,
+        held_keys: Vec<Note>,
+        root: Note,
+        scale: Scale,
+        settings: Option<RandomSettings>,
+    ) -> RandomGenerator {
+        let mut random = Random::from_time();
+        let mut generator = RandomGenerator {
+            instrument: instrument,
+            pattern: pattern,
+            held_keys: held_keys,
+            root: root,
+            scale: scale,
+            settings: RandomSettings {
+                seed: random.next_u64(),
+                density: get_density(3),
+                notes: Vec::new(),
+                min_velocity: 64,
+                max_velocity: 127,
+                mutations: Vec::new(),
+            },
+            pool: None,
+            random: random,
+        };
+        match settings {
+            Some(settings) => generator.settings = settings,
+            None => {
+                let pool = if generator.held_keys.is_empty() { 1 } else { 0 };
+                generator.set_pool(pool);
+            }
+        }
+        return generator;
+    }
+
+    fn set_pool(&mut self, pool: usize) {
+        self.settings.notes = match pool {
+            0 => self.held_keys.clone(),
+            1 => self.scale.get_notes(self.root),
+            _ => POOL_SCALES[pool - 2].get_notes(self.root),
+        };
+        self.pool = Some(pool);
+    }
+
+    fn update_preview(&mut self, context: &mut UIContext) {
+        let preview = random_pattern(&self.settings);
+        context
+            .sequencer
+            .set_preview(Some((self.instrument, self.pattern, preview)));
+    }
+
+    fn handle_note(&mut self, note: u8) -> bool {
+        if let Some(index) = PAD_DENSITY_NOTES.iter().position(|&x| x == note) {
+            self.settings.density = get_density(index);
+        } else if let Some(pool) = PAD_POOL_NOTES.iter().position(|&x| x == note) {
+            if pool == 0 && self.held_keys.is_empty() {
+                return false;
+            }
+            self.set_pool(pool);
+        } else if let Some((_, velocity)) = PAD_MIN_VELOCITY_NOTES.iter().find(|x| x.0 == note) {
+            self.settings.min_velocity = *velocity;
+            self.settings.max_velocity = self.settings.max_velocity.max(*velocity);
+        } else if let Some((_, velocity)) = PAD_MAX_VELOCITY_NOTES.iter().find(|x| x.0 == note) {
+            self.settings.max_velocity = *velocity;
+            self.settings.min_velocity = self.settings.min_velocity.min(*velocity);
+        } else if let Some(index) = PAD_MUTATE_NOTES.iter().position(|&x| x == note) {
+            self.settings.mutations.push(Mutation {
+                seed: self.random.next_u64(),
+                amount: get_density(index),
+            });
+        } else {
+            return false;
+        }
+        return true;
+    }
+}
+
+impl Screen for RandomGenerator {
+    fn handle_pad_events(&mut self, context: &mut UIContext) -> ScreenEvent {
+        while context.pad.has_events() {
+            let event = context.pad.pop_event().unwrap();
+            let message = event.message;
+            if message.velocity == 0 {
+                continue;
+            }
+            match message.r#type {
+                MidiMessageType::ControlChange => match message.note {
+                    PAD_COMMIT_CC => {
+                        let the_pattern = random_pattern(&self.settings);
+                        context
+                            .sequencer
+                            .set_pattern(self.instrument, self.pattern, &the_pattern);
+                        return ScreenEvent::SwitchToPattern(self.instrument, self.pattern);
+                    }
+                    PAD_CANCEL_CC => {
+                        return ScreenEvent::SwitchToPattern(self.instrument, self.pattern);
+                    }
+                    PAD_NEW_SEED_CC => {
+                        self.settings.seed = self.random.next_u64();
+                        self.settings.mutations.clear();
+                        self.update_preview(context);
+                    }
+                    _ => {}
+                },
+                MidiMessageType::NoteOn if self.handle_note(message.note) => {
+                    self.update_preview(context);
+                }
+                _ => {}
+            }
+        }
+        context.pad.send_events();
+        return ScreenEvent::None;
+    }
+
+    fn refresh(&mut self, context: &mut UIContext) {
+        let preview = random_pattern(&self.settings);
+        for step in 0..BAR_SIZE {
+            let color = if preview.has_step_set(step) {
+                PAD_COLOR_STEP
+            } else {
+                PAD_COLOR_REST
+            };
+            let channel = if step == context.sequencer.get_active_step() {
+                3
+            } else {
+                1
+            };
+            context
+                .pad
+                .play_note(channel, PAD_BAR_NOTES[step as usize], color, 0.0);
+        }
+        for (index, note) in PAD_DENSITY_NOTES.iter().enumerate() {
+            let color = if get_density(index) <= self.settings.density {
+                PAD_COLOR_VALUE
+            } else {
+                PAD_COLOR_VALUE_UNSET
+            };
+            context.pad.play_note(1, *note, color, 0.0);
+        }
+        for (pool, note) in PAD_POOL_NOTES.iter().enumerate() {
+            let color = if self.pool == Some(pool) {
+                PAD_COLOR_VALUE
+            } else if pool == 0 && self.held_keys.is_empty() {
+                0
+            } else {
+                PAD_COLOR_VALUE_UNSET
+            };
+            context.pad.play_note(1, *note, color, 0.0);
+        }
+        for (note, velocity) in PAD_MIN_VELOCITY_NOTES {
+            let color = if velocity == self.settings.min_velocity {
+                PAD_COLOR_VALUE
+            } else {
+                PAD_COLOR_VALUE_UNSET
+            };
+            context.pad.play_note(1, note, color, 0.0);
+        }
+        for (note, velocity) in PAD_MAX_VELOCITY_NOTES {
+            let color = if velocity == self.settings.max_velocity {
+                PAD_COLOR_VALUE
+            } else {
+                PAD_COLOR_VALUE_UNSET
+            };
+            context.pad.play_note(1, note, color, 0.0);
+        }
+        for note in PAD_MUTATE_NOTES {
+            context.pad.play_note(1, note, PAD_COLOR_MUTATE, 0.0);
+        }
+        context.pad.send_cc(1, PAD_COMMIT_CC, PAD_COLOR_COMMIT);
+        context.pad.send_cc(1, PAD_CANCEL_CC, PAD_COLOR_CANCEL);
+        context.pad.send_cc(1, PAD_NEW_SEED_CC, PAD_COLOR_NEW_SEED);
+        context
+            .sequencer
+            .set_preview(Some((self.instrument, self.pattern, preview)));
+    }
+
+    fn clear(&mut self, context: &mut UIContext) {
+        context.sequencer.set_preview(None);
+        for note in PAD_BAR_NOTES
+            .iter()
+            .chain(PAD_DENSITY_NOTES.iter())
+            .chain(PAD_POOL_NOTES.iter())
+            .chain(PAD_MUTATE_NOTES.iter())
+        {
+            context.pad.play_note(1, *note, 0, 0.0);
+        }
+        for (note, _) in PAD_MIN_VELOCITY_NOTES
+            .iter()
+            .chain(PAD_MAX_VELOCITY_NOTES.iter())
+        {
+            context.pad.play_note(1, *note, 0, 0.0);
+        }
+        for cc in [PAD_COMMIT_CC, PAD_CANCEL_CC, PAD_NEW_SEED_CC] {
+            context.pad.send_cc(1, cc, 0);
+        }
+    }
+}
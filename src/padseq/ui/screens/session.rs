@@ -1,24 +1,51 @@
 use super::pattern::PAD_BAR_NOTES;
 
 use crate::padseq::midi::MidiMessageType;
-use crate::padseq::session::{Step, BAR_SIZE};
+use crate::padseq::session::{LaunchQuantization, Pattern, Scene, Step, BAR_SIZE};
 use crate::padseq::ui::{Screen, ScreenEvent, UIContext};
 
-// const PAD_COLOR_PATTERN_UNSET: u8 = 103;
+const PAD_COLOR_PATTERN_UNSET: u8 = 103;
 const PAD_COLOR_PATTERN_INACTIVE: u8 = 71;
 const PAD_COLOR_PATTERN_ACTIVE: u8 = 90;
 const PAD_COPY_BUTTON_NOTE: u8 = 17;
 const PAD_EDIT_BUTTON_NOTE: u8 = 18;
+const PAD_MIXER_BUTTON_NOTE: u8 = 11;
+const PAD_ARRANGEMENT_BUTTON_NOTE: u8 = 12;
+const PAD_PROJECTS_BUTTON_NOTE: u8 = 13;
+const PAD_LIBRARY_BUTTON_NOTE: u8 = 14;
+const PAD_COLOR_LIBRARY: u8 = 49;
+const PAD_QUANTIZATION_BUTTON_NOTE: u8 = 16;
+const PAD_COLOR_QUANTIZATION_IMMEDIATE: u8 = 5;
+const PAD_COLOR_QUANTIZATION_BEAT: u8 = 13;
+const PAD_COLOR_QUANTIZATION_BAR: u8 = 21;
+/// Side buttons launching the patterns of their row for all instruments.
+const PAD_SCENE_LAUNCH_CCS: [u8; 4] = [89, 79, 69, 59];
+const PAD_COLOR_SCENE_LAUNCH: u8 = 21;
+const PAD_UP_CC: u8 = 91;
+const PAD_DOWN_CC: u8 = 92;
+const PAD_LEFT_CC: u8 = 93;
+const PAD_RIGHT_CC: u8 = 94;
+const INSTRUMENT_COLUMNS: usize = 8;
+/// One pad per page, the one of the current page is lit.
+const PAD_PAGE_NOTES: [u8; 8] = [41, 42, 43, 44, 45, 46, 47, 48];
+const PAD_COLOR_PAGE: u8 = 45;
+const PAD_COLOR_ARROW: u8 = 41;
+const PATTERN_ROWS: usize = 4;
+const NUMBER_OF_PAGES: usize = PAD_PAGE_NOTES.len();
 
 enum Mode {
     Default,
     Edit,
     Copy,
+    /// A pattern of the library is pasted into the next pressed slot.
+    Paste(Pattern),
 }
 
 pub struct Session {
     mode: Mode,
     copy_source_pattern: Option<(usize, usize)>,
+    page: usize,
+    instrument_page: usize,
 }
 
 impl Session {
@@ -26,21 +53,66 @@ impl Session {
         Session {
             mode: Mode::Default,
             copy_source_pattern: None,
+            page: 0,
+            instrument_page: 0,
         }
     }
 
+    /// Waits for a slot to paste the pattern into.
+    pub fn with_pattern_to_paste(pattern: Pattern) -> Session {
+        let mut session = Session::new();
+        session.mode = Mode::Paste(pattern);
+        return session;
+    }
+
+    /// The instrument and pattern shown on the given step pad.
+    fn get_slot(&self, step: usize) -> (usize, usize) {
+        let column = step % INSTRUMENT_COLUMNS;
+        let row = (step - column) / INSTRUMENT_COLUMNS;
+        return (
+            self.instrument_page * INSTRUMENT_COLUMNS + column,
+            self.page * PATTERN_ROWS + row,
+        );
+    }
+
+    fn number_of_instrument_pages(&self, context: &mut UIContext) -> usize {
+        let count = context.sequencer.get_session().number_of_instruments();
+        return count.div_ceil(INSTRUMENT_COLUMNS);
+    }
+
+    /// Launches the pattern of the row for every instrument at the next bar,
+    /// instruments without a pattern there are stopped.
+    fn launch_scene(&mut self, pattern: usize, context: &mut UIContext) {
+        let session = context.sequencer.get_session();
+        let patterns = (0..session.number_of_instruments())
+            .map(|x| match session.get_instrument(x).has_pattern(pattern) {
+                true => Some(pattern),
+                false => None,
+            })
+            .collect();
+        context.sequencer.launch_scene(&Scene::new(patterns, 1));
+    }
+
     fn refresh_step(&mut self, step: Step, context: &mut UIContext) {
-        let instrument = step as usize % 8;
-        let pattern = (step as usize - instrument) / 8;
+        let (instrument, pattern) = self.get_slot(step as usize);
         let note = PAD_BAR_NOTES[step as usize];
+        if instrument >= context.sequencer.get_session().number_of_instruments() {
+            context.pad.play_note(1, note, 0, 0.0);
+            return;
+        }
         let is_active = context
             .sequencer
             .get_session()
             .get_instrument(instrument)
             .get_active_pattern()
             == Some(pattern);
+        let is_queued = match context.sequencer.get_queued_pattern(instrument) {
+            Some(queued) => queued == Some(pattern) || (queued.is_none() && is_active),
+            None => false,
+        };
 
-        let channel = 1;
+        // queued pads flash until they are launched
+        let channel = if is_queued { 2 } else { 1 };
 
         // let channel = if step == 0 || step == BAR_SIZE - 1 {
         //     3
@@ -50,8 +122,15 @@ impl Session {
 
         let color = if is_active {
             PAD_COLOR_PATTERN_ACTIVE
-        } else {
+        } else if context
+            .sequencer
+            .get_session()
+            .get_instrument(instrument)
+            .has_pattern(pattern)
+        {
             PAD_COLOR_PATTERN_INACTIVE
+        } else {
+            PAD_COLOR_PATTERN_UNSET
         };
         context.pad.play_note(channel, note, color, 0.0);
     }
@@ -65,27 +144,31 @@ impl Screen for Session {
             let note = message.note;
             match message.r#type {
                 MidiMessageType::ControlChange => {
-                    // if message.velocity > 0 {
-                    //     match message.note {
-                    //         PAD_NEXT_CC => {
-                    //             self.instrument =
-                    //                 cmp::min(NUMBER_OF_INSTRUMENTS, self.instrument + 1);
-                    //         }
-                    //         PAD_PREV_CC => {
-                    //             self.instrument = cmp::max(0, self.instrument - 1);
-                    //         }
-                    //         _ => {}
-                    //     }
-                    // }
-                    // println!("{} is new active instrument", self.instrument);
+                    if message.velocity > 0 {
+                        if let Some(row) = PAD_SCENE_LAUNCH_CCS.iter().position(|&x| x == note) {
+                            self.launch_scene(self.page * PATTERN_ROWS + row, context);
+                        } else if note == PAD_UP_CC && self.page > 0 {
+                            self.page -= 1;
+                        } else if note == PAD_DOWN_CC && self.page + 1 < NUMBER_OF_PAGES {
+                            self.page += 1;
+                        } else if note == PAD_LEFT_CC && self.instrument_page > 0 {
+                            self.instrument_page -= 1;
+                        } else if note == PAD_RIGHT_CC
+                            && self.instrument_page + 1 < self.number_of_instrument_pages(context)
+                        {
+                            self.instrument_page += 1;
+                        }
+                    }
                 }
                 _ => {
                     if PAD_BAR_NOTES.contains(&note) {
                         if message.velocity > 0 {
-                            let step =
-                                PAD_BAR_NOTES.iter().position(|&x| x == note).unwrap() as usize;
-                            let instrument = step % 8;
-                            let pattern = (step - instrument) / 8;
+                            let step = PAD_BAR_NOTES.iter().position(|&x| x == note).unwrap();
+                            let (instrument, pattern) = self.get_slot(step);
+                            if instrument >= context.sequencer.get_session().number_of_instruments()
+                            {
+                                continue;
+                            }
                             println!(
                                 "step {}, instrument {}, pattern {}",
                                 step, instrument, pattern
@@ -95,24 +178,42 @@ impl Screen for Session {
                                 return ScreenEvent::SwitchToPattern(instrument, pattern);
                             }
 
+                            if let Mode::Paste(the_pattern) = &self.mode {
+                                context
+                                    .sequencer
+                                    .set_pattern(instrument, pattern, the_pattern);
+                                self.mode = Mode::Default;
+                                continue;
+                            }
+
                             if matches!(&self.mode, Mode::Copy) {
-                                if matches!(&self.copy_source_pattern, None) {
-                                    self.copy_source_pattern = Some((instrument, pattern));
+                                if self.copy_source_pattern.is_none() {
+                                    // empty slots have nothing to copy
+                                    if context
+                                        .sequencer
+                                        .get_session()
+                                        .get_instrument(instrument)
+                                        .has_pattern(pattern)
+                                    {
+                                        self.copy_source_pattern = Some((instrument, pattern));
+                                    }
                                 } else {
                                     let (src_instrument, src_pattern) =
                                         self.copy_source_pattern.unwrap();
-                                    let the_pattern = context
+                                    // the source may have been undone since
+                                    if let Some(the_pattern) = context
                                         .sequencer
                                         .get_session()
                                         .get_instrument(src_instrument)
                                         .get_pattern(src_pattern)
-                                        .unwrap()
-                                        .clone();
-                                    context
-                                        .sequencer
-                                        .get_session_mut()
-                                        .get_instrument_mut(instrument)
-                                        .set_pattern(pattern, &the_pattern);
+                                        .cloned()
+                                    {
+                                        context.sequencer.set_pattern(
+                                            instrument,
+                                            pattern,
+                                            &the_pattern,
+                                        );
+                                    }
                                     self.copy_source_pattern = None;
                                     self.mode = Mode::Default;
                                 }
@@ -127,21 +228,43 @@ impl Screen for Session {
                                 == Some(pattern)
                             {
                                 println!("set none");
-                                context
-                                    .sequencer
-                                    .get_session_mut()
-                                    .get_instrument_mut(instrument)
-                                    .set_active_pattern(None);
+                                context.sequencer.launch_pattern(instrument, None);
                             } else {
                                 println!("set active");
-                                context
-                                    .sequencer
-                                    .get_session_mut()
-                                    .get_instrument_mut(instrument)
-                                    .set_active_pattern(Some(pattern));
+                                context.sequencer.launch_pattern(instrument, Some(pattern));
                             }
+                        }
+                    } else if note == PAD_QUANTIZATION_BUTTON_NOTE {
+                        if message.velocity > 0 {
+                            let session = context.sequencer.get_session_mut();
+                            let quantization = match session.get_launch_quantization() {
+                                LaunchQuantization::Bar => LaunchQuantization::Beat,
+                                LaunchQuantization::Beat => LaunchQuantization::Immediate,
+                                LaunchQuantization::Immediate => LaunchQuantization::Bar,
+                            };
+                            session.set_launch_quantization(quantization);
                             context.sequencer.save_session();
                         }
+                    } else if note == PAD_MIXER_BUTTON_NOTE {
+                        if message.velocity > 0 {
+                            return ScreenEvent::SwitchToMixer;
+                        }
+                    } else if note == PAD_ARRANGEMENT_BUTTON_NOTE {
+                        if message.velocity > 0 {
+                            return ScreenEvent::SwitchToArrangement;
+                        }
+                    } else if note == PAD_PROJECTS_BUTTON_NOTE {
+                        if message.velocity > 0 && context.sequencer.get_projects().is_some() {
+                            return ScreenEvent::SwitchToProjects;
+                        }
+                    } else if note == PAD_LIBRARY_BUTTON_NOTE {
+                        if message.velocity > 0 {
+                            if matches!(&self.mode, Mode::Paste(_)) {
+                                self.mode = Mode::Default;
+                            } else {
+                                return ScreenEvent::SwitchToLibrary;
+                            }
+                        }
                     } else if note == PAD_EDIT_BUTTON_NOTE {
                         if message.velocity > 0 {
                             self.mode = match self.mode {
@@ -149,13 +272,11 @@ impl Screen for Session {
                                 _ => Mode::Edit,
                             };
                         }
-                    } else if note == PAD_COPY_BUTTON_NOTE {
-                        if message.velocity > 0 {
-                            self.mode = match self.mode {
-                                Mode::Copy => Mode::Default,
-                                _ => Mode::Copy,
-                            };
-                        }
+                    } else if note == PAD_COPY_BUTTON_NOTE && message.velocity > 0 {
+                        self.mode = match self.mode {
+                            Mode::Copy => Mode::Default,
+                            _ => Mode::Copy,
+                        };
                     }
                 }
             }
@@ -165,6 +286,9 @@ impl Screen for Session {
     }
 
     fn refresh(&mut self, context: &mut UIContext) {
+        // instruments may have been removed meanwhile
+        let instrument_pages = self.number_of_instrument_pages(context);
+        self.instrument_page = self.instrument_page.min(instrument_pages - 1);
         for n in 0..BAR_SIZE {
             self.refresh_step(n, context);
         }
@@ -188,14 +312,85 @@ impl Screen for Session {
             124,
             0.0,
         );
+        context.pad.play_note(1, PAD_MIXER_BUTTON_NOTE, 21, 0.0);
+        context
+            .pad
+            .play_note(1, PAD_ARRANGEMENT_BUTTON_NOTE, 45, 0.0);
+        if context.sequencer.get_projects().is_some() {
+            context.pad.play_note(1, PAD_PROJECTS_BUTTON_NOTE, 53, 0.0);
+        }
+        context.pad.play_note(
+            if matches!(&self.mode, Mode::Paste(_)) {
+                3
+            } else {
+                1
+            },
+            PAD_LIBRARY_BUTTON_NOTE,
+            PAD_COLOR_LIBRARY,
+            0.0,
+        );
+        context.pad.play_note(
+            1,
+            PAD_QUANTIZATION_BUTTON_NOTE,
+            match context.sequencer.get_session().get_launch_quantization() {
+                LaunchQuantization::Immediate => PAD_COLOR_QUANTIZATION_IMMEDIATE,
+                LaunchQuantization::Beat => PAD_COLOR_QUANTIZATION_BEAT,
+                LaunchQuantization::Bar => PAD_COLOR_QUANTIZATION_BAR,
+            },
+            0.0,
+        );
+        for cc in PAD_SCENE_LAUNCH_CCS {
+            context.pad.send_cc(1, cc, PAD_COLOR_SCENE_LAUNCH);
+        }
+        for (page, note) in PAD_PAGE_NOTES.iter().enumerate() {
+            let color = if page == self.page { PAD_COLOR_PAGE } else { 0 };
+            context.pad.play_note(1, *note, color, 0.0);
+        }
+        let up_color = if self.page > 0 { PAD_COLOR_ARROW } else { 0 };
+        context.pad.send_cc(1, PAD_UP_CC, up_color);
+        let down_color = if self.page + 1 < NUMBER_OF_PAGES {
+            PAD_COLOR_ARROW
+        } else {
+            0
+        };
+        context.pad.send_cc(1, PAD_DOWN_CC, down_color);
+        let left_color = if self.instrument_page > 0 {
+            PAD_COLOR_ARROW
+        } else {
+            0
+        };
+        context.pad.send_cc(1, PAD_LEFT_CC, left_color);
+        let right_color = if self.instrument_page + 1 < instrument_pages {
+            PAD_COLOR_ARROW
+        } else {
+            0
+        };
+        context.pad.send_cc(1, PAD_RIGHT_CC, right_color);
     }
 
     fn clear(&mut self, context: &mut UIContext) {
         for note in PAD_BAR_NOTES {
             context.pad.play_note(1, note, 0, 0.0);
         }
-        for note in [PAD_COPY_BUTTON_NOTE, PAD_EDIT_BUTTON_NOTE] {
+        for note in [
+            PAD_COPY_BUTTON_NOTE,
+            PAD_EDIT_BUTTON_NOTE,
+            PAD_MIXER_BUTTON_NOTE,
+            PAD_ARRANGEMENT_BUTTON_NOTE,
+            PAD_PROJECTS_BUTTON_NOTE,
+            PAD_LIBRARY_BUTTON_NOTE,
+            PAD_QUANTIZATION_BUTTON_NOTE,
+        ] {
+            context.pad.play_note(1, note, 0, 0.0);
+        }
+        for note in PAD_PAGE_NOTES {
             context.pad.play_note(1, note, 0, 0.0);
         }
+        for cc in PAD_SCENE_LAUNCH_CCS
+            .iter()
+            .chain([PAD_UP_CC, PAD_DOWN_CC, PAD_LEFT_CC, PAD_RIGHT_CC].iter())
+        {
+            context.pad.send_cc(1, *cc, 0);
+        }
     }
 }